@@ -0,0 +1,138 @@
+use crate::query::Query;
+use crate::results::SearchResults;
+use crate::{search, Strategy};
+use std::fmt::{Display, Formatter};
+use std::fs;
+
+/// Below this, starting threads costs more than scanning everything on one.
+const SMALL_INPUT_BYTES: u64 = 1 << 20;
+/// Files this big are worth splitting, so more than one thread scans them.
+const HUGE_FILE_BYTES: u64 = 32 << 20;
+
+/// What the strategy is chosen from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workload {
+    pub files: usize,
+    pub total_bytes: u64,
+    pub largest_bytes: u64,
+    pub thread_amount: usize,
+}
+
+/// The strategy `auto` mode picked, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrategyChoice {
+    pub strategy: Strategy,
+    pub workload: Workload,
+    pub reason: &'static str,
+}
+
+/// Picks the strategy that suits the files best. Files whose size cannot be read
+/// count as empty, since the search reports them anyway.
+pub fn choose_strategy(file_names: &[String], thread_amount: usize) -> StrategyChoice {
+    let sizes = file_names
+        .iter()
+        .map(|file_name| fs::metadata(file_name).map_or(0, |metadata| metadata.len()))
+        .collect::<Vec<_>>();
+
+    let workload = Workload {
+        files: file_names.len(),
+        total_bytes: sizes.iter().sum(),
+        largest_bytes: sizes.into_iter().max().unwrap_or(0),
+        thread_amount: thread_amount.max(1),
+    };
+    let (strategy, reason) = decide(&workload);
+
+    StrategyChoice {
+        strategy,
+        workload,
+        reason,
+    }
+}
+
+/// Searches with the strategy `choose_strategy` picks for these files.
+pub fn grep_auto(
+    query: impl Into<Query>,
+    file_names: Vec<String>,
+    thread_amount: usize,
+) -> SearchResults {
+    let choice = choose_strategy(&file_names, thread_amount);
+    search(choice.strategy, query, file_names)
+}
+
+fn decide(workload: &Workload) -> (Strategy, &'static str) {
+    if workload.thread_amount == 1 {
+        return (Strategy::Sequential, "there is a single thread to run on");
+    }
+    if workload.total_bytes < SMALL_INPUT_BYTES {
+        return (
+            Strategy::Sequential,
+            "the input is too small to pay for starting threads",
+        );
+    }
+    if workload.files > workload.thread_amount {
+        // The pool mixes both: whole files keep every thread busy, and the
+        // chunks of the big ones are picked up by whichever thread is free
+        return (
+            Strategy::Pool {
+                thread_amount: workload.thread_amount,
+            },
+            "there are more files than threads",
+        );
+    }
+    if workload.largest_bytes >= HUGE_FILE_BYTES {
+        return (
+            Strategy::Chunked,
+            "a few files, big enough to be split into chunks",
+        );
+    }
+    (Strategy::PerFile, "a few files, each one fits a thread")
+}
+
+impl Display for StrategyChoice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "auto picked {:?}: {} ({} files, {} bytes, largest {} bytes, {} threads)",
+            self.strategy,
+            self.reason,
+            self.workload.files,
+            self.workload.total_bytes,
+            self.workload.largest_bytes,
+            self.workload.thread_amount
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strategy(files: usize, total_bytes: u64, largest_bytes: u64) -> Strategy {
+        decide(&Workload {
+            files,
+            total_bytes,
+            largest_bytes,
+            thread_amount: 4,
+        })
+        .0
+    }
+
+    #[test]
+    fn small_inputs_stay_sequential() {
+        assert_eq!(strategy(100, 1000, 10), Strategy::Sequential);
+        assert_eq!(
+            choose_strategy(&["resources/test1.txt".to_string()], 8).strategy,
+            Strategy::Sequential
+        );
+    }
+
+    #[test]
+    fn strategy_follows_file_count_and_sizes() {
+        assert_eq!(strategy(3, 6 << 20, 2 << 20), Strategy::PerFile);
+        assert_eq!(strategy(2, 100 << 20, 90 << 20), Strategy::Chunked);
+        assert_eq!(
+            strategy(50, 100 << 20, 90 << 20),
+            Strategy::Pool { thread_amount: 4 }
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::query::Query;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Stops a search before it is over. Clones share their state, so cancelling any
+/// of them stops every worker checking the others.
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    /// Its own flag comes last; the ones before belong to the tokens it was made from.
+    flags: Vec<Arc<AtomicBool>>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken {
+            flags: vec![Arc::new(AtomicBool::new(false))],
+        }
+    }
+
+    /// Asks the search to stop. Workers stop at their next line, and files that
+    /// were not over report `GrepError::Cancelled`.
+    pub fn cancel(&self) {
+        if let Some(flag) = self.flags.last() {
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flags.iter().any(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// A token cancelled along with this one, which may also be cancelled on its own.
+    pub(crate) fn child(&self) -> CancelToken {
+        let mut flags = self.flags.clone();
+        flags.push(Arc::new(AtomicBool::new(false)));
+        CancelToken { flags }
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        CancelToken::new()
+    }
+}
+
+/// Stops what is left of a file once its first pieces hold as many matches as its
+/// limit, since no match after them can be reported anymore. Pieces may end in
+/// any order, but only the ones before the first unfinished piece count.
+pub(crate) struct PieceLimit {
+    max_count: Option<usize>,
+    stop: CancelToken,
+    progress: Mutex<LimitProgress>,
+}
+
+#[derive(Default)]
+struct LimitProgress {
+    pending: BTreeMap<usize, usize>,
+    next_piece: usize,
+    matches: usize,
+}
+
+impl PieceLimit {
+    pub fn new(query: &Query, stop: CancelToken) -> PieceLimit {
+        PieceLimit {
+            max_count: query.options.max_count,
+            stop,
+            progress: Mutex::new(LimitProgress::default()),
+        }
+    }
+
+    /// The token workers of the file check, cancelled once the limit is reached.
+    pub fn stop(&self) -> &CancelToken {
+        &self.stop
+    }
+
+    pub fn piece_done(&self, piece: usize, matches: usize) {
+        let Some(max_count) = self.max_count else {
+            return;
+        };
+
+        let progress = &mut *self.progress.lock().unwrap();
+        progress.pending.insert(piece, matches);
+        while let Some(matches) = progress.pending.remove(&progress.next_piece) {
+            progress.matches += matches;
+            progress.next_piece += 1;
+        }
+        if progress.matches >= max_count {
+            self.stop.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::SearchOptions;
+
+    #[test]
+    fn children_are_cancelled_with_their_parent_but_not_the_other_way_around() {
+        let parent = CancelToken::new();
+        let (first, second) = (parent.child(), parent.child());
+
+        first.cancel();
+        assert!(first.is_cancelled());
+        assert!(!parent.is_cancelled() && !second.is_cancelled());
+
+        parent.clone().cancel();
+        assert!(second.is_cancelled());
+    }
+
+    #[test]
+    fn limit_only_counts_pieces_from_the_start() {
+        let query = Query::new(
+            "match",
+            SearchOptions {
+                max_count: Some(2),
+                ..SearchOptions::default()
+            },
+        );
+        let limit = PieceLimit::new(&query, CancelToken::new());
+
+        limit.piece_done(1, 5);
+        assert!(!limit.stop().is_cancelled());
+        limit.piece_done(0, 1);
+        assert!(limit.stop().is_cancelled());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::cancel::CancelToken;
+use crate::pattern::{Pattern, PatternOptions};
+use crate::query::{Query, SearchOptions};
+use crate::results::SearchResults;
+use crate::searcher::{Searcher, DEFAULT_CHUNK_SIZE};
+use crate::stream::{Delivery, MatchStream};
+use crate::{default_thread_amount, search_files, stream_files, Strategy};
+use std::sync::Arc;
+
+/// How the expressions of a `SearchConfig` are read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PatternKind {
+    /// Regular expressions.
+    #[default]
+    Regex,
+    /// Plain text, where every character stands for itself, like `grep -F`.
+    Literal,
+}
+
+/// Everything a search needs but the files, set one option at a time, like
+/// `SearchConfig::new("fo+").case_insensitive(true).context(1).threads(4)`.
+/// Searches run on a pool with as many threads as the machine has, unless another
+/// strategy or searcher is chosen.
+#[derive(Clone)]
+pub struct SearchConfig {
+    expressions: Vec<String>,
+    kind: PatternKind,
+    pattern_options: PatternOptions,
+    options: SearchOptions,
+    searcher: Arc<dyn Searcher>,
+    chunk_size: usize,
+    thread_amount: Option<usize>,
+}
+
+impl SearchConfig {
+    pub fn new(expression: impl Into<String>) -> SearchConfig {
+        SearchConfig::any_of([expression])
+    }
+
+    /// Matches lines matching any of `expressions`. None at all matches nothing.
+    pub fn any_of(expressions: impl IntoIterator<Item = impl Into<String>>) -> SearchConfig {
+        SearchConfig {
+            expressions: expressions.into_iter().map(Into::into).collect(),
+            kind: PatternKind::default(),
+            pattern_options: PatternOptions::default(),
+            options: SearchOptions::default(),
+            searcher: Arc::new(Strategy::Pool {
+                thread_amount: default_thread_amount(),
+            }),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            thread_amount: None,
+        }
+    }
+
+    pub fn kind(mut self, kind: PatternKind) -> SearchConfig {
+        self.kind = kind;
+        self
+    }
+
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> SearchConfig {
+        self.pattern_options.case_insensitive = case_insensitive;
+        self
+    }
+
+    pub fn whole_word(mut self, whole_word: bool) -> SearchConfig {
+        self.pattern_options.whole_word = whole_word;
+        self
+    }
+
+    /// Matches the expressions as plain text within `max_edits` edits, whatever their kind.
+    pub fn fuzzy(mut self, max_edits: Option<usize>) -> SearchConfig {
+        self.pattern_options.fuzzy = max_edits;
+        self
+    }
+
+    /// Lets matches span up to `max_lines` lines, `DEFAULT_MULTILINE_LINES` being a
+    /// good fit, with `^` and `$` matching at the start and end of each of them.
+    pub fn multiline(mut self, max_lines: Option<usize>) -> SearchConfig {
+        self.pattern_options.multiline = max_lines.is_some();
+        self.options.multiline = max_lines;
+        self
+    }
+
+    pub fn invert(mut self, invert: bool) -> SearchConfig {
+        self.options.invert = invert;
+        self
+    }
+
+    pub fn max_count(mut self, max_count: Option<usize>) -> SearchConfig {
+        self.options.max_count = max_count;
+        self
+    }
+
+    /// Lines to report both before and after each match.
+    pub fn context(self, lines: usize) -> SearchConfig {
+        self.before_context(lines).after_context(lines)
+    }
+
+    pub fn before_context(mut self, lines: usize) -> SearchConfig {
+        self.options.before_context = lines;
+        self
+    }
+
+    pub fn after_context(mut self, lines: usize) -> SearchConfig {
+        self.options.after_context = lines;
+        self
+    }
+
+    pub fn decompress(mut self, decompress: bool) -> SearchConfig {
+        self.options.decompress = decompress;
+        self
+    }
+
+    pub fn text(mut self, text: bool) -> SearchConfig {
+        self.options.text = text;
+        self
+    }
+
+    /// Threads to run on, whatever the strategy or searcher: the size of the pools,
+    /// the workers reading byte ranges of a file, and the chunks of a file scanned at
+    /// once by `Chunked`. Searchers otherwise start as many as they see fit.
+    pub fn threads(mut self, thread_amount: usize) -> SearchConfig {
+        self.thread_amount = Some(thread_amount.max(1));
+        self
+    }
+
+    pub fn strategy(self, strategy: Strategy) -> SearchConfig {
+        self.searcher(strategy)
+    }
+
+    /// Splits the search with a searcher of any kind, including ones from other crates.
+    pub fn searcher(mut self, searcher: impl Searcher + 'static) -> SearchConfig {
+        self.searcher = Arc::new(searcher);
+        self
+    }
+
+    /// Lines in each chunk, for searchers that split files into chunks.
+    pub fn chunk_size(mut self, chunk_size: usize) -> SearchConfig {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Compiles the expressions and options into what the lower level functions take.
+    pub fn query(&self) -> Result<Query, regex::Error> {
+        let expressions = match self.kind {
+            PatternKind::Literal if self.pattern_options.fuzzy.is_none() => self
+                .expressions
+                .iter()
+                .map(|expression| regex::escape(expression))
+                .collect(),
+            _ => self.expressions.clone(),
+        };
+        let pattern = Pattern::with_options(&expressions, &self.pattern_options)?;
+        Ok(Query::new(pattern, self.options.clone()))
+    }
+
+    pub fn search(&self, file_names: Vec<String>) -> Result<SearchResults, regex::Error> {
+        self.search_cancellable(file_names, &CancelToken::new())
+    }
+
+    /// Like `search`, returning early once `cancel` is cancelled.
+    pub fn search_cancellable(
+        &self,
+        file_names: Vec<String>,
+        cancel: &CancelToken,
+    ) -> Result<SearchResults, regex::Error> {
+        Ok(search_files(
+            &self.searcher,
+            Arc::new(self.query()?),
+            file_names,
+            self.chunk_size,
+            self.thread_amount,
+            cancel,
+        ))
+    }
+
+    /// Starts the search in the background, like `grep_stream_cancellable`.
+    pub fn stream(
+        &self,
+        file_names: Vec<String>,
+        delivery: Delivery,
+        cancel: &CancelToken,
+    ) -> Result<MatchStream, regex::Error> {
+        Ok(stream_files(
+            self.searcher.clone(),
+            Arc::new(self.query()?),
+            file_names,
+            self.chunk_size,
+            self.thread_amount,
+            delivery,
+            cancel,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::STRATEGIES;
+    use crate::{grep_seq, Chunked, Job, Sequential, WorkStealing};
+    use std::sync::Mutex;
+
+    /// Scans like `Sequential`, keeping the thread amount of every job.
+    #[derive(Clone, Default)]
+    struct Recording(Arc<Mutex<Vec<Option<usize>>>>);
+
+    impl Searcher for Recording {
+        fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+            self.0.lock().unwrap().push(job.thread_amount());
+            Sequential.scan(file_names, job)
+        }
+    }
+
+    #[test]
+    fn configs_search_like_hand_built_queries() {
+        let file_names = vec![
+            "resources/test1.txt".to_string(),
+            "resources/latin1.txt".to_string(),
+        ];
+        let query = Query::new(
+            Pattern::with_options(
+                &["I"],
+                &PatternOptions {
+                    case_insensitive: true,
+                    whole_word: false,
+                    fuzzy: None,
+                    multiline: false,
+                },
+            )
+            .unwrap(),
+            SearchOptions {
+                max_count: Some(2),
+                before_context: 1,
+                after_context: 1,
+                ..SearchOptions::default()
+            },
+        );
+        let expected = grep_seq(query, file_names.clone()).matches;
+
+        for chunk_size in [1, 2, 1000] {
+            let result = SearchConfig::new("I")
+                .case_insensitive(true)
+                .max_count(Some(2))
+                .context(1)
+                .searcher(Chunked)
+                .chunk_size(chunk_size)
+                .search(file_names.clone())
+                .unwrap();
+            assert_eq!(result.matches, expected, "chunk size {}", chunk_size);
+        }
+
+        let dots = SearchConfig::new(".").kind(PatternKind::Literal).threads(2);
+        assert!(dots.search(file_names).unwrap().matches.is_empty());
+        assert!(SearchConfig::new("(").query().is_err());
+    }
+
+    #[test]
+    fn threads_are_kept_whatever_the_searcher() {
+        let file_names = vec!["resources/test1.txt".to_string()];
+        let expected = grep_seq("i", file_names.clone()).matches;
+
+        let recording = Recording::default();
+        for config in [
+            SearchConfig::new("i")
+                .searcher(recording.clone())
+                .threads(3),
+            SearchConfig::new("i")
+                .threads(3)
+                .searcher(recording.clone()),
+        ] {
+            assert_eq!(config.search(file_names.clone()).unwrap().matches, expected);
+        }
+        assert_eq!(*recording.0.lock().unwrap(), [Some(3), Some(3)]);
+
+        let stealing = WorkStealing::new(5);
+        let config = SearchConfig::new("i").threads(2).searcher(stealing.clone());
+        assert_eq!(config.search(file_names).unwrap().matches, expected);
+        assert_eq!(stealing.reports()[0].workers.len(), 2);
+    }
+
+    #[test]
+    fn fuzzy_matches_report_their_edits_in_every_strategy() {
+        let file_names = vec!["resources/test1.txt".to_string()];
+        for strategy in STRATEGIES {
+            let result = SearchConfig::new("multi-threading")
+                .fuzzy(Some(2))
+                .case_insensitive(true)
+                .strategy(strategy)
+                .chunk_size(2)
+                .search(file_names.clone())
+                .unwrap();
+
+            assert_eq!(result.matches.len(), 1, "{:?}", strategy);
+            assert_eq!(result.matches[0].line_number, 5);
+            assert_eq!(
+                result.matches[0].matched_texts().collect::<Vec<_>>(),
+                ["multithreading"]
+            );
+            assert_eq!(result.matches[0].edits(), Some(1));
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::matches::{ContextLine, Match, Span};
+
+/// Consecutive lines of a file to be shown together: one or more matches plus
+/// their context, with the context shared by close matches appearing only once.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContextGroup<'a> {
+    pub path: &'a str,
+    pub lines: Vec<GroupLine<'a>>,
+}
+
+/// A line of a `ContextGroup`, either a match or context around one.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GroupLine<'a> {
+    pub line_number: usize,
+    pub byte_offset: u64,
+    /// The line, or every line of a match spanning several of them, joined by `\n`.
+    pub line: &'a str,
+    /// Where the pattern matched inside `line`. Empty for context lines.
+    pub spans: &'a [Span],
+    pub is_match: bool,
+}
+
+/// Groups matches with their context, like grep does before separating groups
+/// with `--`. Groups of the same file that overlap or touch are merged into one.
+pub fn context_groups(matches: &[Match]) -> Vec<ContextGroup<'_>> {
+    let mut grouper = ContextGrouper::default();
+    let mut groups: Vec<ContextGroup> = vec![];
+
+    for found in matches {
+        let (starts_group, lines) = grouper.push(found);
+        match groups.last_mut() {
+            Some(group) if !starts_group => group.lines.extend(lines),
+            _ => groups.push(ContextGroup {
+                path: &found.path,
+                lines,
+            }),
+        }
+    }
+
+    groups
+}
+
+/// Groups matches one at a time, for output printed while a search is still running.
+#[derive(Debug, Default)]
+pub struct ContextGrouper {
+    /// File and number of the last line handed out.
+    last: Option<(String, usize)>,
+}
+
+impl ContextGrouper {
+    /// Returns the lines of `found` and its context that previous matches did not
+    /// already give, and whether they start a new group.
+    pub fn push<'a>(&mut self, found: &'a Match) -> (bool, Vec<GroupLine<'a>>) {
+        let lines = found
+            .before
+            .iter()
+            .map(as_group_line)
+            .chain([GroupLine {
+                line_number: found.line_number,
+                byte_offset: found.byte_offset,
+                line: &found.line,
+                spans: &found.spans,
+                is_match: true,
+            }])
+            .chain(found.after.iter().map(as_group_line));
+        let first_line = found
+            .before
+            .first()
+            .map_or(found.line_number, |line| line.line_number);
+        let last_line = found
+            .after
+            .last()
+            .map_or(found.end_line_number(), |line| line.line_number);
+
+        // The same file may be searched twice, which starts over from its first line.
+        // A multiline match may start in the line where the previous one ended.
+        let previous = match &self.last {
+            Some((path, line_number))
+                if *path == found.path && found.end_line_number() > *line_number =>
+            {
+                Some(*line_number)
+            }
+            _ => None,
+        };
+        match &mut self.last {
+            Some((path, line_number)) if *path == found.path => *line_number = last_line,
+            _ => self.last = Some((found.path.clone(), last_line)),
+        }
+
+        match previous {
+            Some(previous) => (
+                first_line > previous + 1,
+                lines
+                    .filter(|line| line.end_line_number() > previous)
+                    .collect(),
+            ),
+            None => (true, lines.collect()),
+        }
+    }
+}
+
+impl GroupLine<'_> {
+    /// Number of the last line of `line`, which only differs from `line_number` for
+    /// multiline matches.
+    pub fn end_line_number(&self) -> usize {
+        self.line_number + self.line.matches('\n').count()
+    }
+}
+
+fn as_group_line(line: &ContextLine) -> GroupLine<'_> {
+    GroupLine {
+        line_number: line.line_number,
+        byte_offset: line.byte_offset,
+        line: &line.line,
+        spans: &[],
+        is_match: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grep_seq, Pattern, Query, SearchOptions};
+
+    fn summary(groups: Vec<ContextGroup>) -> Vec<Vec<(usize, bool)>> {
+        groups
+            .into_iter()
+            .map(|group| {
+                group
+                    .lines
+                    .iter()
+                    .map(|line| (line.line_number, line.is_match))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn close_matches_share_a_group() {
+        let query = Query::new(
+            "help",
+            SearchOptions {
+                after_context: 1,
+                ..SearchOptions::default()
+            },
+        );
+        let results = grep_seq(query, vec!["resources/test1.txt".to_string()]);
+
+        assert_eq!(
+            summary(context_groups(&results.matches)),
+            vec![vec![(3, true), (4, true), (5, false)]]
+        );
+    }
+
+    #[test]
+    fn files_searched_twice_are_not_merged() {
+        let query = Query::new(
+            "help",
+            SearchOptions {
+                after_context: 1,
+                ..SearchOptions::default()
+            },
+        );
+        let file = "resources/test1.txt".to_string();
+        let results = grep_seq(query, vec![file.clone(), file]);
+
+        assert_eq!(summary(context_groups(&results.matches)).len(), 2);
+    }
+
+    #[test]
+    fn distant_matches_start_new_groups() {
+        let query = Query::new(
+            Pattern::regex("This|We").unwrap(),
+            SearchOptions {
+                before_context: 1,
+                ..SearchOptions::default()
+            },
+        );
+        let results = grep_seq(query, vec!["resources/test1.txt".to_string()]);
+
+        assert_eq!(
+            summary(context_groups(&results.matches)),
+            vec![vec![(1, true)], vec![(4, false), (5, true)]]
+        );
+    }
+}
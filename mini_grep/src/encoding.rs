@@ -0,0 +1,234 @@
+use std::borrow::Cow;
+use std::char::REPLACEMENT_CHARACTER;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// How many leading bytes are sniffed for a NUL byte to tell binary files apart, like grep.
+pub(crate) const BINARY_SNIFF_SIZE: usize = 8 * 1024;
+
+pub(crate) const UTF8_BOM: &str = "\u{feff}";
+const UTF16_LE_BOM: [u8; 2] = [0xff, 0xfe];
+const UTF16_BE_BOM: [u8; 2] = [0xfe, 0xff];
+/// Code units transcoded at once.
+const DECODE_UNITS: usize = 4 * 1024;
+
+/// Text read as it is, or transcoded to UTF-8 while it is read when it started with
+/// a UTF-16 byte order mark.
+pub(crate) enum Text<R> {
+    Utf8(R),
+    Utf16(BufReader<Utf16Decoder<R>>),
+}
+
+/// Turns UTF-16 into UTF-8. Unpaired surrogates, and a last byte without its pair,
+/// become U+FFFD like `String::from_utf16_lossy` does.
+pub(crate) struct Utf16Decoder<R> {
+    reader: R,
+    big_endian: bool,
+    /// UTF-8 transcoded but not read yet, from `position` on.
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+impl<R: BufRead> Text<R> {
+    /// Peeks at the first bytes of `reader` for a UTF-16 byte order mark, and for a NUL
+    /// byte telling that it is binary, which is returned as well.
+    pub fn sniff(mut reader: R) -> io::Result<(Text<R>, bool)> {
+        let head = reader.fill_buf()?;
+        let big_endian = if head.starts_with(&UTF16_LE_BOM) {
+            false
+        } else if head.starts_with(&UTF16_BE_BOM) {
+            true
+        } else {
+            let binary = looks_binary(head);
+            return Ok((Text::Utf8(reader), binary));
+        };
+
+        reader.consume(2);
+        let decoder = Utf16Decoder {
+            reader,
+            big_endian,
+            decoded: vec![],
+            position: 0,
+        };
+        Ok((Text::Utf16(BufReader::new(decoder)), false))
+    }
+}
+
+/// Whether the first bytes of a file hold a NUL byte. UTF-16 text is full of them,
+/// so it is only binary when it has no byte order mark.
+pub(crate) fn looks_binary(head: &[u8]) -> bool {
+    let head = &head[..head.len().min(BINARY_SNIFF_SIZE)];
+    !is_utf16(head) && head.contains(&0)
+}
+
+/// Whether the first bytes of a file are a UTF-16 byte order mark.
+pub(crate) fn is_utf16(head: &[u8]) -> bool {
+    head.starts_with(&UTF16_LE_BOM) || head.starts_with(&UTF16_BE_BOM)
+}
+
+/// Whether the file at `path` starts with a UTF-16 byte order mark. Files that
+/// cannot be read are not: the search will report them.
+pub(crate) fn is_utf16_file(path: impl AsRef<Path>) -> bool {
+    let mut bom = [0; 2];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut bom))
+        .is_ok_and(|()| is_utf16(&bom))
+}
+
+/// Turns the bytes of a line into text. Lines that are not valid UTF-8 are taken
+/// as Latin-1, where every byte is a character of its own, so they never fail.
+/// The first line of a file loses its UTF-8 byte order mark, if it has one.
+pub(crate) fn decode_line(bytes: Vec<u8>, is_first: bool) -> String {
+    let text = String::from_utf8(bytes)
+        .unwrap_or_else(|err| err.into_bytes().iter().map(|&byte| byte as char).collect());
+    match text.strip_prefix(UTF8_BOM) {
+        Some(rest) if is_first => rest.to_string(),
+        _ => text,
+    }
+}
+
+/// Like `decode_line`, borrowing the bytes unless they are not UTF-8, so that lines
+/// read into a buffer that is reused are not copied.
+pub(crate) fn decode_bytes(bytes: &[u8], is_first: bool) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if is_first => Cow::Borrowed(text.strip_prefix(UTF8_BOM).unwrap_or(text)),
+        Ok(text) => Cow::Borrowed(text),
+        // Latin-1 has no byte order mark to strip
+        Err(_) => Cow::Owned(bytes.iter().map(|&byte| byte as char).collect()),
+    }
+}
+
+impl<R: BufRead> Utf16Decoder<R> {
+    fn decode_more(&mut self) -> io::Result<()> {
+        let mut units = Vec::with_capacity(DECODE_UNITS + 1);
+        while units.len() < DECODE_UNITS {
+            match self.next_unit()? {
+                Some(unit) => units.push(unit),
+                None => break,
+            }
+        }
+        // A surrogate pair is not cut in two
+        if units
+            .last()
+            .is_some_and(|unit| (0xd800..0xdc00).contains(unit))
+        {
+            units.extend(self.next_unit()?);
+        }
+
+        self.decoded.clear();
+        self.position = 0;
+        let mut encoded = [0; 4];
+        for c in char::decode_utf16(units) {
+            let c = c.unwrap_or(REPLACEMENT_CHARACTER);
+            self.decoded
+                .extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+        }
+        Ok(())
+    }
+
+    fn next_unit(&mut self) -> io::Result<Option<u16>> {
+        let mut bytes = [0; 2];
+        let mut read = 0;
+        while read < 2 {
+            match self.reader.read(&mut bytes[read..]) {
+                Ok(0) => break,
+                Ok(amount) => read += amount,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(match read {
+            0 => None,
+            1 => Some(REPLACEMENT_CHARACTER as u16),
+            _ if self.big_endian => Some(u16::from_be_bytes(bytes)),
+            _ => Some(u16::from_le_bytes(bytes)),
+        })
+    }
+}
+
+impl<R: BufRead> Read for Utf16Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.decoded.len() {
+            self.decode_more()?;
+        }
+        let amount = buf.len().min(self.decoded.len() - self.position);
+        buf[..amount].copy_from_slice(&self.decoded[self.position..self.position + amount]);
+        self.position += amount;
+        Ok(amount)
+    }
+}
+
+impl<R: BufRead> Read for Text<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Text::Utf8(reader) => reader.read(buf),
+            Text::Utf16(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Text<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Text::Utf8(reader) => reader.fill_buf(),
+            Text::Utf16(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            Text::Utf8(reader) => reader.consume(amount),
+            Text::Utf16(reader) => reader.consume(amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_text(bytes: Vec<u8>) -> (String, bool) {
+        let (mut text, binary) = Text::sniff(Cursor::new(bytes)).unwrap();
+        let mut decoded = vec![];
+        text.read_to_end(&mut decoded).unwrap();
+        (String::from_utf8(decoded).unwrap(), binary)
+    }
+
+    #[test]
+    fn utf16_is_transcoded_and_nul_bytes_are_binary() {
+        let text = "déjà vu 𝄞\r\nline two\n";
+        let mut little_endian = UTF16_LE_BOM.to_vec();
+        let mut big_endian = UTF16_BE_BOM.to_vec();
+        for unit in text.encode_utf16() {
+            little_endian.extend(unit.to_le_bytes());
+            big_endian.extend(unit.to_be_bytes());
+        }
+        assert_eq!(read_text(little_endian), (text.to_string(), false));
+        assert_eq!(read_text(big_endian), (text.to_string(), false));
+
+        assert_eq!(
+            read_text(b"plain\n".to_vec()),
+            ("plain\n".to_string(), false)
+        );
+        assert!(read_text(b"ELF\0\x01\x02".to_vec()).1);
+    }
+
+    #[test]
+    fn lines_that_are_not_utf8_are_latin1() {
+        assert_eq!(
+            decode_line(b"Caf\xe9 au lait".to_vec(), false),
+            "Café au lait"
+        );
+        assert_eq!(decode_line("Café".as_bytes().to_vec(), false), "Café");
+        assert_eq!(decode_line(b"\xef\xbb\xbfstart".to_vec(), true), "start");
+        assert_eq!(decode_bytes(b"Caf\xe9", false), "Café");
+        assert!(matches!(
+            decode_bytes(b"\xef\xbb\xbfstart", true),
+            Cow::Borrowed("start")
+        ));
+    }
+}
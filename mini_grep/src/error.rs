@@ -0,0 +1,70 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// Why a single file could not be searched. Failures are per file:
+/// the rest of the search goes on and reports them next to the matches.
+#[derive(Debug)]
+pub enum GrepError {
+    NotFound(String),
+    PermissionDenied(String),
+    /// A line is not UTF-8 where it has to be, like one to replace in, which was
+    /// transcoded to be searched, so writing the replacement would change its encoding.
+    InvalidUtf8 {
+        path: String,
+        line_number: usize,
+    },
+    WorkerPanic(String),
+    /// The search was cancelled before the file was over.
+    Cancelled(String),
+    Io {
+        path: String,
+        source: io::Error,
+    },
+}
+
+impl GrepError {
+    pub(crate) fn from_io(path: &str, line_number: usize, err: io::Error) -> GrepError {
+        let path = path.to_string();
+        match err.kind() {
+            io::ErrorKind::NotFound => GrepError::NotFound(path),
+            io::ErrorKind::PermissionDenied => GrepError::PermissionDenied(path),
+            io::ErrorKind::InvalidData => GrepError::InvalidUtf8 { path, line_number },
+            _ => GrepError::Io { path, source: err },
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            GrepError::NotFound(path)
+            | GrepError::PermissionDenied(path)
+            | GrepError::InvalidUtf8 { path, .. }
+            | GrepError::WorkerPanic(path)
+            | GrepError::Cancelled(path)
+            | GrepError::Io { path, .. } => path,
+        }
+    }
+}
+
+impl Display for GrepError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrepError::NotFound(path) => write!(f, "{}: No such file or directory", path),
+            GrepError::PermissionDenied(path) => write!(f, "{}: Permission denied", path),
+            GrepError::InvalidUtf8 { path, line_number } => {
+                write!(f, "{}:{}: Line is not valid UTF-8", path, line_number)
+            }
+            GrepError::WorkerPanic(path) => write!(f, "{}: Worker thread panicked", path),
+            GrepError::Cancelled(path) => write!(f, "{}: Search was cancelled", path),
+            GrepError::Io { path, source } => write!(f, "{}: {}", path, source),
+        }
+    }
+}
+
+impl std::error::Error for GrepError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GrepError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
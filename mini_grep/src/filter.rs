@@ -0,0 +1,259 @@
+use crate::matches::{ContextLine, Match};
+use crate::query::Query;
+use std::collections::VecDeque;
+use std::mem;
+
+/// Decides which lines of a file, or of a piece of it, are reported. Lines have to
+/// be pushed in order; the filter keeps the context lines and the per-file limit.
+pub(crate) struct LineFilter<'a> {
+    path: &'a str,
+    query: &'a Query,
+    /// Whether the file looks binary, which every match of it tells.
+    binary: bool,
+    before: VecDeque<ContextLine>,
+    /// Last match, while it still collects its after context.
+    awaiting_after: Option<Match>,
+    matches: Vec<Match>,
+    selected: usize,
+}
+
+impl<'a> LineFilter<'a> {
+    pub fn new(path: &'a str, query: &'a Query, binary: bool) -> LineFilter<'a> {
+        LineFilter {
+            path,
+            query,
+            binary: binary && !query.options.text,
+            before: VecDeque::new(),
+            awaiting_after: None,
+            matches: vec![],
+            selected: 0,
+        }
+    }
+
+    /// Returns `false` once nothing else can be reported, so callers may stop reading.
+    pub fn push(&mut self, line_number: usize, byte_offset: u64, text: &str) -> bool {
+        if self.is_limit_reached() {
+            self.push_context(line_number, byte_offset, text);
+            return self.wants_context();
+        }
+
+        match self.select(line_number, byte_offset, text) {
+            Some(found) => self.push_selected(found),
+            None => self.push_unselected(ContextLine::new(line_number, byte_offset, text)),
+        }
+    }
+
+    /// Like `push`, for a match the caller found, which may span several lines.
+    pub fn push_selected(&mut self, mut found: Match) -> bool {
+        if self.is_limit_reached() {
+            self.skip_selected();
+            return self.wants_context();
+        }
+
+        // Context belongs to the closest match, so the next one cuts it short
+        self.matches.extend(self.awaiting_after.take());
+        found.before = self.before.drain(..).collect();
+        found.binary = self.binary;
+        self.selected += 1;
+        if self.query.options.after_context > 0 {
+            self.awaiting_after = Some(found);
+        } else {
+            self.matches.push(found);
+        }
+
+        !self.is_limit_reached() || self.wants_context()
+    }
+
+    /// Like `push`, for a line the caller found not to be selected.
+    pub fn push_unselected(&mut self, line: ContextLine) -> bool {
+        self.add_context(line);
+        !self.is_limit_reached() || self.wants_context()
+    }
+
+    /// Pushes a line that is never reported as a match, because it belongs to a
+    /// neighbouring piece of the file (or comes after the limit), but may still be
+    /// context of the matches around it.
+    pub fn push_context(&mut self, line_number: usize, byte_offset: u64, text: &str) {
+        if self.query.pattern.is_match(text) != self.query.options.invert {
+            self.skip_selected();
+        } else {
+            self.add_context(ContextLine::new(line_number, byte_offset, text));
+        }
+    }
+
+    /// Pushes a match that is never reported, because it belongs to a neighbouring
+    /// piece of the file (or comes after the limit), but cuts the context around it short.
+    pub fn skip_selected(&mut self) {
+        self.matches.extend(self.awaiting_after.take());
+        self.before.clear();
+    }
+
+    /// Whether the last match still needs lines after it.
+    pub fn wants_context(&self) -> bool {
+        self.awaiting_after.is_some()
+    }
+
+    /// Takes the matches whose context is already complete.
+    pub fn take_ready(&mut self) -> Vec<Match> {
+        mem::take(&mut self.matches)
+    }
+
+    pub fn finish(mut self) -> Vec<Match> {
+        self.matches.extend(self.awaiting_after);
+        self.matches
+    }
+
+    fn is_limit_reached(&self) -> bool {
+        self.query
+            .options
+            .max_count
+            .is_some_and(|max_count| self.selected >= max_count)
+    }
+
+    fn select(&self, line_number: usize, byte_offset: u64, text: &str) -> Option<Match> {
+        let spans = if self.query.options.invert {
+            if self.query.pattern.is_match(text) {
+                return None;
+            }
+            vec![]
+        } else {
+            let spans = self.query.pattern.find_spans(text);
+            if spans.is_empty() {
+                return None;
+            }
+            spans
+        };
+
+        Some(Match::new(self.path, line_number, byte_offset, text, spans))
+    }
+
+    /// Pushes a line that is not selected, whichever piece of the file it belongs to.
+    pub fn add_context(&mut self, line: ContextLine) {
+        let before_context = self.query.options.before_context;
+        if before_context > 0 {
+            self.before.push_back(line.clone());
+            if self.before.len() > before_context {
+                self.before.pop_front();
+            }
+        }
+
+        let Some(awaiting) = self.awaiting_after.as_mut() else {
+            return;
+        };
+        awaiting.after.push(line);
+
+        if awaiting.after.len() >= self.query.options.after_context {
+            self.matches.extend(self.awaiting_after.take());
+        }
+    }
+}
+
+/// Joins the matches of consecutive pieces of a file, applying the per-file limit
+/// that each piece could only apply on its own.
+pub(crate) fn merge_pieces(
+    pieces: impl IntoIterator<Item = Vec<Match>>,
+    query: &Query,
+) -> Vec<Match> {
+    let matches = pieces.into_iter().flatten();
+    match query.options.max_count {
+        Some(max_count) => matches.take(max_count).collect(),
+        None => matches.collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::SearchOptions;
+
+    const LINES: [&str; 8] = ["a", "match 1", "b", "c", "d", "match 2", "match 3", "e"];
+
+    fn filter(options: SearchOptions) -> Vec<(usize, Vec<usize>, Vec<usize>)> {
+        let query = Query::new("match", options);
+        let mut filter = LineFilter::new("file", &query, false);
+        for (index, line) in LINES.iter().enumerate() {
+            if !filter.push(index + 1, 0, line) {
+                break;
+            }
+        }
+
+        let numbers = |lines: &[ContextLine]| lines.iter().map(|line| line.line_number).collect();
+        filter
+            .finish()
+            .iter()
+            .map(|found| {
+                (
+                    found.line_number,
+                    numbers(&found.before),
+                    numbers(&found.after),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn context_stops_at_the_next_match() {
+        let options = SearchOptions {
+            before_context: 1,
+            after_context: 2,
+            ..SearchOptions::default()
+        };
+
+        assert_eq!(
+            filter(options),
+            vec![
+                (2, vec![1], vec![3, 4]),
+                (6, vec![5], vec![]),
+                (7, vec![], vec![8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_count_keeps_trailing_context() {
+        let options = SearchOptions {
+            max_count: Some(1),
+            after_context: 1,
+            ..SearchOptions::default()
+        };
+
+        assert_eq!(filter(options), vec![(2, vec![], vec![3])]);
+    }
+
+    #[test]
+    fn neighbouring_lines_only_give_context() {
+        let query = Query::new(
+            "match",
+            SearchOptions {
+                before_context: 2,
+                after_context: 2,
+                ..SearchOptions::default()
+            },
+        );
+        let mut filter = LineFilter::new("file", &query, false);
+        filter.push_context(1, 0, "a");
+        filter.push_context(2, 0, "match 1");
+        filter.push_context(3, 0, "b");
+        filter.push(4, 0, "match 2");
+        filter.push(5, 0, "c");
+        filter.push_context(6, 0, "match 3");
+        filter.push_context(7, 0, "d");
+
+        let matches = filter.finish();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].before, vec![ContextLine::new(3, 0, "b")]);
+        assert_eq!(matches[0].after, vec![ContextLine::new(5, 0, "c")]);
+    }
+
+    #[test]
+    fn invert_selects_the_other_lines() {
+        let options = SearchOptions {
+            invert: true,
+            max_count: Some(4),
+            ..SearchOptions::default()
+        };
+
+        let selected = filter(options).into_iter().map(|(line, _, _)| line);
+        assert_eq!(selected.collect::<Vec<_>>(), vec![1, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,499 @@
+use crate::cancel::CancelToken;
+use crate::encoding::{decode_line, Text};
+use crate::error::GrepError;
+use crate::filter::LineFilter;
+use crate::gzip::Input;
+use crate::lines::NumberedLine;
+use crate::matches::Match;
+use crate::query::Query;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Seek};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Something a followed file reports. Every file begins, then reports its matches
+/// and what happened to it, and only ends once it is no longer followed.
+#[derive(Debug)]
+pub enum FollowEvent {
+    Begin {
+        path: String,
+    },
+    Match(Match),
+    /// The file got shorter than what was already read, so it is read again from its start.
+    Truncated {
+        path: String,
+    },
+    /// Another file took the place of the followed one, which is followed from its start.
+    Rotated {
+        path: String,
+    },
+    /// The file is no longer followed: the search was cancelled, the per-file limit was
+    /// reached, or it could not be read anymore.
+    End {
+        path: String,
+        matches: usize,
+        error: Option<GrepError>,
+    },
+}
+
+/// Matches of files that are still growing, handed out as soon as they are written.
+/// It only ends once every file stopped being followed, which usually means the
+/// search was cancelled. Dropping it cancels the search, and waits for every file
+/// to stop being followed.
+pub struct FollowStream {
+    events: Receiver<FollowEvent>,
+    cancel: CancelToken,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// How a followed file changed since the last poll, apart from growing.
+enum Change {
+    None,
+    Truncated,
+    Rotated,
+}
+
+/// A file being followed, and how far it was read.
+struct FollowedFile {
+    file: File,
+    id: Option<(u64, u64)>,
+    /// What the file holds, decompressed and transcoded like any searched file. It is
+    /// only told how to read once the file has its first bytes.
+    text: Option<Text<Input<BufReader<File>>>>,
+    decompress: bool,
+    binary: bool,
+    utf16: bool,
+    /// Bytes read from the file so far, including the last line if it is incomplete.
+    read_bytes: u64,
+    /// Last bytes read, which do not make a whole line yet.
+    partial: Vec<u8>,
+    line_number: usize,
+    line_offset: u64,
+}
+
+/// Searches what every file already holds, like `tail -f` then keeps checking every
+/// `poll_interval` for lines appended to them, until `cancel` is cancelled.
+///
+/// Every file is followed on a thread of its own, which notices when the file is
+/// truncated or replaced by another one, as log rotation does. Its first bytes tell
+/// whether it is compressed, UTF-16 or binary, like they do for searched files. Matches are reported
+/// once their context is complete, so those asking for lines after them wait for those.
+pub fn grep_follow(
+    query: impl Into<Query>,
+    file_names: Vec<String>,
+    poll_interval: Duration,
+    cancel: &CancelToken,
+) -> FollowStream {
+    let query = Arc::new(query.into());
+    let (tx, rx) = channel();
+    // Dropping the stream only cancels its own search, not whatever else `cancel` stops
+    let cancel = cancel.child();
+
+    let threads = file_names
+        .into_iter()
+        .map(|file_name| {
+            let (query, cancel, tx) = (query.clone(), cancel.clone(), tx.clone());
+            thread::spawn(move || {
+                follow_file(Arc::from(file_name), query, poll_interval, cancel, tx)
+            })
+        })
+        .collect();
+
+    FollowStream {
+        events: rx,
+        cancel,
+        threads,
+    }
+}
+
+impl FollowStream {
+    /// A token that stops following every file, for instance from another thread.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+impl Iterator for FollowStream {
+    type Item = FollowEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.recv().ok()
+    }
+}
+
+impl Drop for FollowStream {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        // Threads notice within a poll, and can no longer block sending events
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn follow_file(
+    path: Arc<str>,
+    query: Arc<Query>,
+    poll_interval: Duration,
+    cancel: CancelToken,
+    events: Sender<FollowEvent>,
+) {
+    let _ = events.send(FollowEvent::Begin {
+        path: path.to_string(),
+    });
+
+    let mut matches = 0;
+    let result = watch(&path, &query, poll_interval, &cancel, |event| {
+        if let FollowEvent::Match(_) = event {
+            matches += 1;
+        }
+        let _ = events.send(event);
+    });
+
+    let error = match result {
+        Ok(()) if cancel.is_cancelled() => Some(GrepError::Cancelled(path.to_string())),
+        Ok(()) => None,
+        Err(err) => Some(err),
+    };
+    let _ = events.send(FollowEvent::End {
+        path: path.to_string(),
+        matches,
+        error,
+    });
+}
+
+/// Reads whatever was appended to `path` on every poll, until there is nothing
+/// else to report or the search is cancelled.
+fn watch(
+    path: &str,
+    query: &Query,
+    poll_interval: Duration,
+    cancel: &CancelToken,
+    mut on_event: impl FnMut(FollowEvent),
+) -> Result<(), GrepError> {
+    let mut followed = FollowedFile::open(path, query.options.decompress)?;
+    // Made for the first lines, once the file told whether it is binary
+    let mut filter = None;
+
+    loop {
+        let lines = followed.read_lines(path)?;
+        if !push_lines(&mut filter, lines, &mut on_event, || {
+            LineFilter::new(path, query, followed.binary)
+        }) || cancel.is_cancelled()
+        {
+            break;
+        }
+
+        thread::sleep(poll_interval);
+
+        let change = followed
+            .change(path)
+            .map_err(|err| GrepError::from_io(path, followed.line_number, err))?;
+        if let Change::None = change {
+            continue;
+        }
+
+        // Whatever was written before the change still belongs to the file that was followed
+        let mut lines = followed.read_lines(path)?;
+        lines.extend(followed.take_partial_line());
+        if !push_lines(&mut filter, lines, &mut on_event, || {
+            LineFilter::new(path, query, followed.binary)
+        }) {
+            break;
+        }
+        finish(filter.take(), &mut on_event);
+
+        match change {
+            Change::Truncated => {
+                on_event(FollowEvent::Truncated {
+                    path: path.to_string(),
+                });
+                followed.restart();
+            }
+            _ => {
+                on_event(FollowEvent::Rotated {
+                    path: path.to_string(),
+                });
+                followed = FollowedFile::open(path, query.options.decompress)?;
+            }
+        }
+    }
+
+    finish(filter, &mut on_event);
+    Ok(())
+}
+
+/// Pushes `lines` into `filter`, making it for the first ones. Returns `false` once
+/// nothing else can be reported.
+fn push_lines<'a>(
+    filter: &mut Option<LineFilter<'a>>,
+    lines: Vec<NumberedLine>,
+    on_event: &mut impl FnMut(FollowEvent),
+    new_filter: impl FnOnce() -> LineFilter<'a>,
+) -> bool {
+    if lines.is_empty() {
+        return true;
+    }
+    let filter = filter.get_or_insert_with(new_filter);
+    for line in lines {
+        let keep_reading = filter.push(line.number, line.offset, &line.text);
+        filter
+            .take_ready()
+            .into_iter()
+            .for_each(|found| on_event(FollowEvent::Match(found)));
+        if !keep_reading {
+            return false;
+        }
+    }
+    true
+}
+
+fn finish(filter: Option<LineFilter>, on_event: &mut impl FnMut(FollowEvent)) {
+    filter
+        .into_iter()
+        .flat_map(LineFilter::finish)
+        .for_each(|found| on_event(FollowEvent::Match(found)));
+}
+
+impl FollowedFile {
+    fn open(path: &str, decompress: bool) -> Result<FollowedFile, GrepError> {
+        let to_grep_error = |err| GrepError::from_io(path, 0, err);
+        let file = File::open(path).map_err(to_grep_error)?;
+        let id = file_id(&file.metadata().map_err(to_grep_error)?);
+
+        Ok(FollowedFile {
+            file,
+            id,
+            text: None,
+            decompress,
+            binary: false,
+            utf16: false,
+            read_bytes: 0,
+            partial: vec![],
+            line_number: 0,
+            line_offset: 0,
+        })
+    }
+
+    /// Reads what was appended since the last call, returning the lines that are
+    /// complete. The last one is kept until its newline is written.
+    fn read_lines(&mut self, path: &str) -> Result<Vec<NumberedLine>, GrepError> {
+        let line_number = self.line_number + 1;
+        let to_grep_error = |err| GrepError::from_io(path, line_number, err);
+        if self.text.is_none() {
+            if self.file.metadata().map_err(to_grep_error)?.len() == 0 {
+                return Ok(vec![]);
+            }
+            self.sniff().map_err(to_grep_error)?;
+        }
+
+        let text = self.text.as_mut().unwrap();
+        text.read_to_end(&mut self.partial).map_err(to_grep_error)?;
+        // The reader shares its position with the file
+        self.read_bytes = self.file.stream_position().map_err(to_grep_error)?;
+
+        let mut lines = vec![];
+        while let Some(end) = self.partial.iter().position(|&byte| byte == b'\n') {
+            let rest = self.partial.split_off(end + 1);
+            let line = std::mem::replace(&mut self.partial, rest);
+            lines.push(self.next_line(line));
+        }
+        Ok(lines)
+    }
+
+    /// Tells from the first bytes of the file whether it is compressed, UTF-16 or
+    /// binary, like searched files are.
+    fn sniff(&mut self) -> io::Result<()> {
+        let reader = BufReader::new(self.file.try_clone()?);
+        let (text, binary) = Text::sniff(Input::sniff(reader, self.decompress)?)?;
+        self.utf16 = matches!(text, Text::Utf16(_));
+        self.binary = binary;
+        if self.utf16 {
+            // Past the byte order mark
+            self.line_offset = 2;
+        }
+        self.text = Some(text);
+        Ok(())
+    }
+
+    /// The last line, even if it has no newline yet, since nothing will be appended to it.
+    fn take_partial_line(&mut self) -> Option<NumberedLine> {
+        if self.partial.is_empty() {
+            return None;
+        }
+        let line = std::mem::take(&mut self.partial);
+        Some(self.next_line(line))
+    }
+
+    fn next_line(&mut self, mut line: Vec<u8>) -> NumberedLine {
+        self.line_number += 1;
+        let offset = self.line_offset;
+        self.line_offset += match self.utf16 {
+            true => 2 * String::from_utf8_lossy(&line).encode_utf16().count() as u64,
+            false => line.len() as u64,
+        };
+
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        NumberedLine {
+            number: self.line_number,
+            offset,
+            text: decode_line(line, self.line_number == 1),
+        }
+    }
+
+    /// A missing path is not a change: a rotated file is usually moved away
+    /// before the next one is created.
+    fn change(&self, path: &str) -> std::io::Result<Change> {
+        if self.file.metadata()?.len() < self.read_bytes {
+            return Ok(Change::Truncated);
+        }
+        match fs::metadata(path) {
+            Ok(metadata) if file_id(&metadata) != self.id => Ok(Change::Rotated),
+            _ => Ok(Change::None),
+        }
+    }
+
+    /// Reads the same file again from its start, which may now hold another kind of text.
+    fn restart(&mut self) {
+        // Seeking to the start of a regular file cannot fail
+        let _ = self.file.rewind();
+        self.text = None;
+        self.binary = false;
+        self.utf16 = false;
+        self.read_bytes = 0;
+        self.partial.clear();
+        self.line_number = 0;
+        self.line_offset = 0;
+    }
+}
+
+/// Tells files apart even when they have the same path, as rotated logs do.
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Without inodes, files replaced under the same path are not told apart.
+#[cfg(not(unix))]
+fn file_id(_: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn next_match(stream: &mut FollowStream) -> (usize, String) {
+        loop {
+            match stream.next() {
+                Some(FollowEvent::Match(found)) => return (found.line_number, found.line),
+                Some(FollowEvent::End { error, .. }) => panic!("ended early: {:?}", error),
+                Some(_) => {}
+                None => panic!("the stream ended"),
+            }
+        }
+    }
+
+    fn append(path: &std::path::Path, text: &str) {
+        let mut file = fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn followed_files_are_read_like_searched_ones() {
+        let dir =
+            std::env::temp_dir().join(format!("mini_grep_follow_text_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let utf16 = dir.join("utf16.log");
+        fs::write(&utf16, "").unwrap();
+        let binary = dir.join("binary.log");
+        fs::write(&binary, "\0\nmatch\n").unwrap();
+
+        let cancel = CancelToken::new();
+        let file_names = [&utf16, &binary].map(|path| path.to_string_lossy().into_owned());
+        let stream = grep_follow(
+            "match",
+            file_names.to_vec(),
+            Duration::from_millis(5),
+            &cancel,
+        );
+        // UTF-16 is only told apart once the file has its first bytes
+        let mut appended = vec![0xff, 0xfe];
+        "café\nmatch\n"
+            .encode_utf16()
+            .for_each(|unit| appended.extend(unit.to_le_bytes()));
+        fs::write(&utf16, appended).unwrap();
+
+        let mut found = vec![];
+        for event in stream {
+            if let FollowEvent::Match(found_match) = event {
+                found.push(found_match);
+                if found.len() == 2 {
+                    cancel.cancel();
+                }
+            }
+        }
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        let found = found
+            .iter()
+            .map(|found| {
+                (
+                    found.line_number,
+                    found.byte_offset,
+                    &*found.line,
+                    found.binary,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, [(2, 2, "match", true), (2, 12, "match", false)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn follows_appended_lines_truncation_and_rotation() {
+        let dir = std::env::temp_dir().join(format!("mini_grep_follow_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "match 1\nother\n").unwrap();
+
+        let cancel = CancelToken::new();
+        let file_names = vec![path.to_string_lossy().into_owned()];
+        let mut stream = grep_follow("match", file_names, Duration::from_millis(5), &cancel);
+        assert_eq!(next_match(&mut stream), (1, "match 1".to_string()));
+
+        append(&path, "match 2\nmat");
+        assert_eq!(next_match(&mut stream), (3, "match 2".to_string()));
+        append(&path, "ch 3\n");
+        assert_eq!(next_match(&mut stream), (4, "match 3".to_string()));
+
+        fs::write(&path, "match 4\n").unwrap();
+        assert_eq!(next_match(&mut stream), (1, "match 4".to_string()));
+
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        fs::write(&path, "match 5\n").unwrap();
+        assert_eq!(next_match(&mut stream), (1, "match 5".to_string()));
+
+        cancel.cancel();
+        let rest = stream.collect::<Vec<_>>();
+        assert!(matches!(
+            rest.last(),
+            Some(FollowEvent::End {
+                error: Some(GrepError::Cancelled(_)),
+                ..
+            })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
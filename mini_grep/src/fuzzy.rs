@@ -0,0 +1,368 @@
+use crate::matches::Span;
+use std::collections::HashMap;
+
+/// Longest expression, in characters, whose columns fit the bits of a word.
+/// Longer ones are matched one cell at a time instead.
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Text matched approximately: a line matches when some part of it can be turned
+/// into one of the expressions with at most `max_edits` insertions, deletions or
+/// substitutions of a character.
+#[derive(Debug, Clone)]
+pub struct Fuzzy {
+    expressions: Vec<Expression>,
+    pub(crate) max_edits: usize,
+    case_insensitive: bool,
+    whole_word: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Expression {
+    text: String,
+    chars: Vec<char>,
+    /// For every character of the expression, the bits of the positions it is at.
+    /// Only built for expressions that fit a word.
+    positions: Option<HashMap<char, u64>>,
+}
+
+impl Fuzzy {
+    pub(crate) fn new(
+        expressions: &[impl AsRef<str>],
+        max_edits: usize,
+        case_insensitive: bool,
+        whole_word: bool,
+    ) -> Fuzzy {
+        let expressions = expressions
+            .iter()
+            .map(|expression| {
+                let text = expression.as_ref().to_string();
+                let chars = text
+                    .chars()
+                    .map(|c| fold(c, case_insensitive))
+                    .collect::<Vec<_>>();
+                let positions = (chars.len() <= WORD_BITS).then(|| {
+                    let mut positions = HashMap::<char, u64>::new();
+                    for (position, &c) in chars.iter().enumerate() {
+                        *positions.entry(c).or_default() |= 1 << position;
+                    }
+                    positions
+                });
+                Expression {
+                    text,
+                    chars,
+                    positions,
+                }
+            })
+            .collect();
+
+        Fuzzy {
+            expressions,
+            max_edits,
+            case_insensitive,
+            whole_word,
+        }
+    }
+
+    /// The expressions as they were given.
+    pub(crate) fn texts(&self) -> impl Iterator<Item = &str> {
+        self.expressions
+            .iter()
+            .map(|expression| expression.text.as_str())
+    }
+
+    pub(crate) fn case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    pub(crate) fn is_match(&self, line: &str) -> bool {
+        if self.whole_word {
+            return !self.find_spans(line).is_empty();
+        }
+        let text = self.chars_of(line);
+        self.expressions.iter().any(|expression| {
+            expression.chars.len() <= self.max_edits
+                || end_edits(expression, &text)
+                    .into_iter()
+                    .any(|edits| edits <= self.max_edits)
+        })
+    }
+
+    /// Every non-overlapping part of `line` within `max_edits` of an expression.
+    /// Each one ends where the fewest edits are needed, and starts where as few are
+    /// needed for that end, as early as possible, so `DataBase` is all of a match
+    /// for `database` within 2 edits, rather than `ataBase`.
+    pub(crate) fn find_spans(&self, line: &str) -> Vec<Span> {
+        let text = self.chars_of(line);
+        let offsets = line
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain([line.len()])
+            .collect::<Vec<_>>();
+
+        let mut spans = vec![];
+        for (index, expression) in self.expressions.iter().enumerate() {
+            for (start, end, edits) in self.find_in(expression, &text) {
+                spans.push(Span {
+                    start: offsets[start],
+                    end: offsets[end],
+                    pattern: index,
+                    edits: Some(edits),
+                });
+            }
+        }
+
+        // Expressions are searched one after another, so their spans are merged by
+        // position, keeping the first one to start wherever they overlap
+        spans.sort_by_key(|span| (span.start, span.pattern));
+        let mut kept: Vec<Span> = vec![];
+        for span in spans {
+            if kept.last().is_none_or(|last| last.end <= span.start) {
+                kept.push(span);
+            }
+        }
+        kept
+    }
+
+    /// Character ranges, with their edits, matching `expression` inside `text`.
+    fn find_in(&self, expression: &Expression, text: &[char]) -> Vec<(usize, usize, usize)> {
+        let length = expression.chars.len();
+        // Whole words start and end next to something that is not part of a word
+        let is_boundary = |position: usize| {
+            !self.whole_word
+                || position == 0
+                || position == text.len()
+                || is_word(text[position - 1]) != is_word(text[position])
+        };
+        if length == 0 {
+            // An empty expression matches with no edits, as a whole word only where no
+            // word is next to it
+            let is_apart = |position: usize| {
+                (position == 0 || !is_word(text[position - 1]))
+                    && (position == text.len() || !is_word(text[position]))
+            };
+            return (0..=text.len())
+                .find(|&position| !self.whole_word || is_apart(position))
+                .map(|position| (position, position, 0))
+                .into_iter()
+                .collect();
+        }
+        if length <= self.max_edits && !self.whole_word {
+            // Deleting every character of the expression is enough, so even empty lines match
+            return vec![(0, 0, length)];
+        }
+
+        let edits = end_edits(expression, text);
+        let mut found = vec![];
+        let mut searched_to = 0;
+        let mut position = 0;
+        while position < edits.len() {
+            if edits[position] > self.max_edits {
+                position += 1;
+                continue;
+            }
+            // Out of a run of ends that are close enough, the best one is kept
+            let mut best: Option<(usize, usize, usize)> = None;
+            while position < edits.len() && edits[position] <= self.max_edits {
+                let end = position + 1;
+                position += 1;
+                if !is_boundary(end) || best.is_some_and(|best| best.2 <= edits[end - 1]) {
+                    continue;
+                }
+                let earliest = searched_to.max(end.saturating_sub(length + self.max_edits));
+                let (start, start_edits) =
+                    best_start(&expression.chars, &text[earliest..end], |start| {
+                        is_boundary(earliest + start)
+                    });
+                // Another match may have taken the start this one needed
+                if start_edits <= self.max_edits && best.is_none_or(|best| start_edits < best.2) {
+                    best = Some((earliest + start, end, start_edits));
+                }
+            }
+            if let Some(best) = best {
+                searched_to = best.1;
+                found.push(best);
+            }
+        }
+        found
+    }
+
+    fn chars_of(&self, line: &str) -> Vec<char> {
+        line.chars()
+            .map(|c| fold(c, self.case_insensitive))
+            .collect()
+    }
+}
+
+fn fold(c: char, case_insensitive: bool) -> char {
+    if case_insensitive {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// For every position of `text`, the fewest edits that turn some part of `text`
+/// ending right after it into the expression.
+fn end_edits(expression: &Expression, text: &[char]) -> Vec<usize> {
+    if expression.chars.is_empty() {
+        return vec![0; text.len()];
+    }
+    match &expression.positions {
+        Some(positions) => myers(expression.chars.len(), positions, text),
+        None => sellers(&expression.chars, text),
+    }
+}
+
+/// Myers' bit-parallel algorithm: a column of the edit distance table is kept as the
+/// bits of the vertical differences between its cells, which are all +1, -1 or 0,
+/// so each character of the text updates the whole column in a few word operations.
+fn myers(length: usize, positions: &HashMap<char, u64>, text: &[char]) -> Vec<usize> {
+    let last = 1u64 << (length - 1);
+    // Differences are +1 all the way down in the first column
+    let mut positive = u64::MAX;
+    let mut negative = 0u64;
+    let mut edits = length;
+
+    text.iter()
+        .map(|c| {
+            let equal = positions.get(c).copied().unwrap_or(0);
+            let vertical = equal | negative;
+            let horizontal = ((equal & positive).wrapping_add(positive) ^ positive) | equal;
+            let mut horizontal_positive = negative | !(horizontal | positive);
+            let mut horizontal_negative = positive & horizontal;
+
+            if horizontal_positive & last != 0 {
+                edits += 1;
+            } else if horizontal_negative & last != 0 {
+                edits -= 1;
+            }
+
+            // A match may start anywhere, so the top row stays 0 instead of growing
+            horizontal_positive <<= 1;
+            horizontal_negative <<= 1;
+            positive = horizontal_negative | !(vertical | horizontal_positive);
+            negative = horizontal_positive & vertical;
+            edits
+        })
+        .collect()
+}
+
+/// Sellers' algorithm, the same table one cell at a time, for expressions too long
+/// for a word.
+fn sellers(expression: &[char], text: &[char]) -> Vec<usize> {
+    let mut column = (0..=expression.len()).collect::<Vec<_>>();
+    text.iter()
+        .map(|&c| {
+            let mut diagonal = column[0];
+            for (row, &expected) in expression.iter().enumerate() {
+                let cell = (diagonal + (expected != c) as usize)
+                    .min(column[row] + 1)
+                    .min(column[row + 1] + 1);
+                diagonal = column[row + 1];
+                column[row + 1] = cell;
+            }
+            column[expression.len()]
+        })
+        .collect()
+}
+
+/// Where a match ending at the end of `text` starts, out of the starts `allowed`,
+/// with the fewest edits, and as early as possible among those.
+fn best_start(
+    expression: &[char],
+    text: &[char],
+    allowed: impl Fn(usize) -> bool,
+) -> (usize, usize) {
+    // The table is filled backwards from the end, so every column tells the edits
+    // needed when the match starts one character earlier
+    let mut column = (0..=expression.len()).collect::<Vec<_>>();
+    let mut best = match allowed(text.len()) {
+        true => (text.len(), expression.len()),
+        false => (text.len(), usize::MAX),
+    };
+    for (taken, &c) in text.iter().rev().enumerate() {
+        let mut diagonal = column[0];
+        column[0] = taken + 1;
+        for (row, &expected) in expression.iter().rev().enumerate() {
+            let cell = (diagonal + (expected != c) as usize)
+                .min(column[row] + 1)
+                .min(column[row + 1] + 1);
+            diagonal = column[row + 1];
+            column[row + 1] = cell;
+        }
+        let start = text.len() - taken - 1;
+        if column[expression.len()] <= best.1 && allowed(start) {
+            best = (start, column[expression.len()]);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(expressions: &[&str], max_edits: usize, line: &str) -> Vec<(String, usize)> {
+        Fuzzy::new(expressions, max_edits, false, false)
+            .find_spans(line)
+            .into_iter()
+            .map(|span| (line[span.start..span.end].to_string(), span.edits.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parts_within_the_edits_match_with_their_distance() {
+        assert_eq!(
+            spans(&["receive"], 1, "we recive, receeve and receive"),
+            [
+                ("recive".to_string(), 1),
+                ("receeve".to_string(), 1),
+                ("receive".to_string(), 0)
+            ]
+        );
+        assert!(spans(&["receive"], 1, "we revived it").is_empty());
+        assert_eq!(
+            spans(&["naïve"], 1, "a naive café"),
+            [("naive".to_string(), 1)]
+        );
+        assert_eq!(spans(&["ab"], 2, ""), [(String::new(), 2)]);
+
+        let fuzzy = Fuzzy::new(&["ERROR", "warning"], 1, true, true);
+        assert!(fuzzy.is_match("an eror here"));
+        assert!(fuzzy.is_match("Warnin: disk"));
+        assert!(fuzzy.is_match("two errors"));
+        assert!(!fuzzy.is_match("terrorism"));
+    }
+
+    #[test]
+    fn empty_expressions_match_without_edits() {
+        assert_eq!(spans(&[""], 0, "abc"), [(String::new(), 0)]);
+
+        let whole_words = Fuzzy::new(&[""], 0, false, true);
+        assert!(whole_words.is_match(""));
+        assert!(whole_words.is_match("a, b"));
+        assert!(!whole_words.is_match("word"));
+        assert_eq!(whole_words.find_spans("ab  cd")[0].start, 3);
+    }
+
+    #[test]
+    fn long_expressions_find_what_short_ones_do() {
+        let short = "the quick brown fox";
+        let long = short.repeat(4);
+        let line = format!(
+            "xx {} yy",
+            long.replacen('q', "k", 1).replacen("fox", "fx", 1)
+        );
+
+        assert_eq!(spans(&[short], 2, &line).len(), 4);
+        assert_eq!(
+            spans(&[&long], 2, &line),
+            [(long.replacen('q', "k", 1).replacen("fox", "fx", 1), 2)]
+        );
+        assert!(spans(&[&long], 1, &line).is_empty());
+    }
+}
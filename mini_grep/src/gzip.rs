@@ -0,0 +1,89 @@
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// First bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Text read as it is, or decompressed while it is read when it turned out to be gzip.
+pub(crate) enum Input<R> {
+    Plain(R),
+    Gzip(BufReader<MultiGzDecoder<R>>),
+}
+
+impl<R: BufRead> Input<R> {
+    /// Peeks at the first bytes of `reader` to tell whether it is compressed,
+    /// without consuming them. Only sniffs when `decompress` is set.
+    pub fn sniff(mut reader: R, decompress: bool) -> io::Result<Input<R>> {
+        if decompress && reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            // Rotated logs are often several gzip members one after another
+            return Ok(Input::Gzip(BufReader::new(MultiGzDecoder::new(reader))));
+        }
+        Ok(Input::Plain(reader))
+    }
+}
+
+/// Whether the file at `path` starts like a gzip stream. Files that cannot be
+/// read are not: the search will report them.
+pub(crate) fn is_gzip(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0; 2];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| magic == GZIP_MAGIC)
+}
+
+impl<R: BufRead> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Plain(reader) => reader.read(buf),
+            Input::Gzip(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Input<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Input::Plain(reader) => reader.fill_buf(),
+            Input::Gzip(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            Input::Plain(reader) => reader.consume(amount),
+            Input::Gzip(reader) => reader.consume(amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_gzip_streams_are_decompressed() {
+        let mut text = String::new();
+        let compressed = BufReader::new(File::open("resources/test1.txt.gz").unwrap());
+        let mut input = Input::sniff(compressed, true).unwrap();
+        assert!(matches!(input, Input::Gzip(_)));
+        input.read_to_string(&mut text).unwrap();
+        assert_eq!(
+            text,
+            std::fs::read_to_string("resources/test1.txt").unwrap()
+        );
+
+        let plain = BufReader::new(File::open("resources/test1.txt").unwrap());
+        assert!(matches!(
+            Input::sniff(plain, true).unwrap(),
+            Input::Plain(_)
+        ));
+        let compressed = BufReader::new(File::open("resources/test1.txt.gz").unwrap());
+        assert!(matches!(
+            Input::sniff(compressed, false).unwrap(),
+            Input::Plain(_)
+        ));
+    }
+}
@@ -0,0 +1,448 @@
+use crate::error::GrepError;
+use crate::pooling::WorkerPool;
+use crate::query::Query;
+use required::{lowercase, Required, Trigram};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::time::UNIX_EPOCH;
+
+mod required;
+
+/// First bytes of every index file, changed whenever its layout does.
+const MAGIC: &[u8] = b"mini_grep trigram index 1\n";
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+/// One bit per possible trigram.
+const TRIGRAM_BITS: usize = 1 << 24;
+
+thread_local! {
+    /// Trigrams seen in the file being indexed, reused by every file a thread indexes
+    /// since clearing only the bits that were set is much cheaper than allocating it.
+    static SEEN: RefCell<Vec<u64>> = RefCell::new(vec![0; TRIGRAM_BITS / 64]);
+}
+
+/// Which trigrams, sequences of three bytes, each file holds. A line can only match
+/// a pattern if its file holds every trigram of the literal parts of that pattern,
+/// so a search can skip the files that lack them without reading them.
+///
+/// ASCII letters are lowercased, so the same index serves case-insensitive searches.
+/// Files are told by their path as it was given when indexing them.
+#[derive(Debug, Default)]
+pub struct TrigramIndex {
+    files: HashMap<String, IndexedFile>,
+}
+
+#[derive(Debug)]
+struct IndexedFile {
+    stamp: Stamp,
+    /// Sorted. Files that are not UTF-8 text, like compressed ones, have none,
+    /// since what a search reads from them is not what they hold.
+    trigrams: Option<Vec<Trigram>>,
+}
+
+/// Size and modification time of a file, which tell whether it changed since it was indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+/// What `update_index` did.
+#[derive(Debug)]
+pub struct IndexUpdate {
+    pub index: TrigramIndex,
+    /// Files read because they were new or had changed.
+    pub indexed: usize,
+    /// Files kept as they were, since they had not changed.
+    pub reused: usize,
+    /// Files that were indexed before but were not given this time.
+    pub dropped: usize,
+    pub errors: Vec<GrepError>,
+}
+
+impl TrigramIndex {
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The files among `file_names` that may have a line matching `query`, in the same
+    /// order, or `None` when the index cannot tell, so that every file must be searched.
+    /// That is the case for inverted searches and patterns without literal parts of
+    /// at least three bytes, like `a.b` or `\w+`.
+    ///
+    /// Files that are not in the index, or that changed since they were indexed,
+    /// are always kept.
+    pub fn candidates(&self, query: &Query, file_names: &[String]) -> Option<Vec<String>> {
+        if query.options.invert {
+            return None;
+        }
+        let required = Required::of(&query.pattern);
+        if required == Required::Anything {
+            return None;
+        }
+
+        Some(
+            file_names
+                .iter()
+                .filter(|file_name| self.may_match(file_name, &required))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    fn may_match(&self, file_name: &str, required: &Required) -> bool {
+        let Some(IndexedFile {
+            stamp,
+            trigrams: Some(trigrams),
+        }) = self.files.get(file_name)
+        else {
+            return true;
+        };
+        let unchanged = fs::metadata(file_name)
+            .ok()
+            .and_then(|metadata| Stamp::of(&metadata))
+            == Some(*stamp);
+        !unchanged || required.holds(trigrams)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<TrigramIndex> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = vec![0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data(
+                "Not a mini_grep index, or one of another version",
+            ));
+        }
+
+        let mut files = HashMap::new();
+        for _ in 0..read_u64(&mut reader)? {
+            let mut path = vec![0; read_u32(&mut reader)? as usize];
+            reader.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| invalid_data("Invalid path"))?;
+            let stamp = Stamp {
+                size: read_u64(&mut reader)?,
+                modified_secs: read_u64(&mut reader)?,
+                modified_nanos: read_u32(&mut reader)?,
+            };
+            let trigrams = match read_u32(&mut reader)? {
+                u32::MAX => None,
+                amount => {
+                    let mut packed = vec![0; amount as usize * 3];
+                    reader.read_exact(&mut packed)?;
+                    Some(
+                        packed
+                            .chunks_exact(3)
+                            .map(|bytes| u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+                            .collect(),
+                    )
+                }
+            };
+            files.insert(path, IndexedFile { stamp, trigrams });
+        }
+        Ok(TrigramIndex { files })
+    }
+
+    /// Writes the index to a temporary file next to `path`, which then takes its place,
+    /// so searches running meanwhile never read half an index.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_file_name(format!(
+            ".{}.mini_grep-{}.tmp",
+            path.file_name()
+                .map_or("".into(), |name| name.to_string_lossy()),
+            std::process::id()
+        ));
+
+        let written = self
+            .write(&temporary)
+            .and_then(|()| fs::rename(&temporary, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        written
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.files.len() as u64).to_le_bytes())?;
+
+        let mut paths = self.files.keys().collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let file = &self.files[path];
+            writer.write_all(&(path.len() as u32).to_le_bytes())?;
+            writer.write_all(path.as_bytes())?;
+            writer.write_all(&file.stamp.size.to_le_bytes())?;
+            writer.write_all(&file.stamp.modified_secs.to_le_bytes())?;
+            writer.write_all(&file.stamp.modified_nanos.to_le_bytes())?;
+            match &file.trigrams {
+                None => writer.write_all(&u32::MAX.to_le_bytes())?,
+                Some(trigrams) => {
+                    writer.write_all(&(trigrams.len() as u32).to_le_bytes())?;
+                    for trigram in trigrams {
+                        writer.write_all(&trigram.to_be_bytes()[1..])?;
+                    }
+                }
+            }
+        }
+
+        writer.into_inner()?.sync_all()
+    }
+}
+
+impl Stamp {
+    fn of(metadata: &fs::Metadata) -> Option<Stamp> {
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// Brings `index` up to date with the given files, reading on `thread_amount` threads
+/// only those that are new or whose size or modification time changed. Files indexed
+/// before but not given anymore are dropped, and so are those that cannot be read.
+pub fn update_index(
+    index: TrigramIndex,
+    file_names: Vec<String>,
+    thread_amount: usize,
+) -> IndexUpdate {
+    let mut previous = index.files;
+    let mut files = HashMap::new();
+    let mut errors = vec![];
+    let mut reused = 0;
+
+    let pool = WorkerPool::new(thread_amount);
+    let (tx, rx) = channel();
+    let mut queued = vec![];
+    let mut seen = HashSet::new();
+    for file_name in file_names {
+        if !seen.insert(file_name.clone()) {
+            continue;
+        }
+        // Taken before reading, so a file changing meanwhile is read again next time
+        let stamp = match fs::metadata(&file_name) {
+            Ok(metadata) => Stamp::of(&metadata),
+            Err(err) => {
+                previous.remove(&file_name);
+                errors.push(GrepError::from_io(&file_name, 0, err));
+                continue;
+            }
+        };
+
+        match (previous.remove(&file_name), stamp) {
+            // Without a modification time, it could never be known to be up to date
+            (_, None) => {}
+            (Some(indexed), Some(stamp)) if indexed.stamp == stamp => {
+                files.insert(file_name, indexed);
+                reused += 1;
+            }
+            (_, Some(stamp)) => {
+                queued.push(file_name.clone());
+                let tx = tx.clone();
+                pool.spawn(move || {
+                    let trigrams = index_file(&file_name);
+                    let _ = tx.send((file_name, stamp, trigrams));
+                });
+            }
+        }
+    }
+    drop(tx);
+    pool.join();
+
+    let mut indexed = rx
+        .into_iter()
+        .map(|(file_name, stamp, trigrams)| (file_name, (stamp, trigrams)))
+        .collect::<HashMap<_, _>>();
+    let mut indexed_files = 0;
+    for file_name in queued {
+        // A worker that panicked never reported back
+        match indexed.remove(&file_name) {
+            Some((stamp, Ok(trigrams))) => {
+                files.insert(file_name, IndexedFile { stamp, trigrams });
+                indexed_files += 1;
+            }
+            Some((_, Err(err))) => errors.push(GrepError::from_io(&file_name, 0, err)),
+            None => errors.push(GrepError::WorkerPanic(file_name)),
+        }
+    }
+
+    IndexUpdate {
+        index: TrigramIndex { files },
+        indexed: indexed_files,
+        reused,
+        dropped: previous.len(),
+        errors,
+    }
+}
+
+/// Reads a file once, collecting its trigrams while checking that it is UTF-8 text.
+fn index_file(path: &str) -> io::Result<Option<Vec<Trigram>>> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    // Bytes of a character cut by the end of the last read, kept at the start of the buffer
+    let mut kept = 0;
+    let mut is_text = true;
+    let mut window = 0u32;
+    let mut window_len = 0;
+    let mut trigrams = vec![];
+
+    SEEN.with(|seen| {
+        let seen = &mut *seen.borrow_mut();
+        let read = loop {
+            let read = match file.read(&mut buffer[kept..]) {
+                Ok(0) => break Ok(()),
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => break Err(err),
+            };
+
+            for &byte in &buffer[kept..kept + read] {
+                window = (window << 8 | lowercase(byte) as u32) & 0xFF_FFFF;
+                window_len += 1;
+                let (word, bit) = (window as usize / 64, 1 << (window % 64));
+                if window_len >= 3 && seen[word] & bit == 0 {
+                    seen[word] |= bit;
+                    trigrams.push(window);
+                }
+            }
+
+            let filled = kept + read;
+            kept = 0;
+            match std::str::from_utf8(&buffer[..filled]) {
+                Ok(_) => {}
+                Err(err) if err.error_len().is_none() => {
+                    buffer.copy_within(err.valid_up_to()..filled, 0);
+                    kept = filled - err.valid_up_to();
+                }
+                // Whatever it holds is never searched as it is
+                Err(_) => {
+                    is_text = false;
+                    break Ok(());
+                }
+            }
+        };
+
+        for &trigram in &trigrams {
+            seen[trigram as usize / 64] = 0;
+        }
+        read
+    })?;
+
+    if !is_text || kept > 0 {
+        return Ok(None);
+    }
+    trigrams.sort_unstable();
+    Ok(Some(trigrams))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{Pattern, PatternOptions};
+    use crate::query::SearchOptions;
+
+    #[test]
+    fn updates_are_incremental_and_survive_saving() {
+        let dir = std::env::temp_dir().join(format!("mini_grep_index_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("a.txt"), "the quick brown fox\n").unwrap();
+        fs::write(path("b.txt"), "jumps over the lazy dog\n").unwrap();
+        fs::write(path("c.bin"), [0x1f, 0x8b, 0xff, b'f', b'o', b'x']).unwrap();
+        let file_names = vec![path("a.txt"), path("b.txt"), path("c.bin")];
+
+        let first = update_index(TrigramIndex::default(), file_names.clone(), 2);
+        assert!(first.errors.is_empty(), "{:?}", first.errors);
+        assert_eq!((first.indexed, first.reused), (3, 0));
+        first.index.save(path("index")).unwrap();
+
+        let index = TrigramIndex::load(path("index")).unwrap();
+        let query = Query::from(Pattern::regex("qu?ick|dogs?").unwrap());
+        assert_eq!(
+            index.candidates(&query, &file_names),
+            Some(vec![path("a.txt"), path("b.txt"), path("c.bin")])
+        );
+        let query = Query::from(Pattern::literal("fox"));
+        assert_eq!(
+            index.candidates(&query, &file_names),
+            Some(vec![path("a.txt"), path("c.bin")])
+        );
+        let inverted = Query::new(
+            Pattern::literal("fox"),
+            SearchOptions {
+                invert: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(index.candidates(&inverted, &file_names), None);
+
+        // A file changed after indexing is searched until the index is updated again
+        fs::write(path("b.txt"), "the lazy fox sleeps\n").unwrap();
+        assert_eq!(
+            index.candidates(&query, &file_names),
+            Some(vec![path("a.txt"), path("b.txt"), path("c.bin")])
+        );
+        let second = update_index(index, vec![path("b.txt"), path("a.txt")], 2);
+        assert_eq!((second.indexed, second.reused, second.dropped), (1, 1, 1));
+        let query = Query::from(Pattern::literal("sleep"));
+        assert_eq!(
+            second.index.candidates(&query, &file_names),
+            Some(vec![path("b.txt"), path("c.bin")])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multiline_patterns_keep_files_with_crlf_line_endings() {
+        let dir = std::env::temp_dir().join(format!("mini_grep_crlf_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("crlf.txt").to_string_lossy().into_owned();
+        fs::write(&path, "foo\r\nbar\r\n").unwrap();
+        let file_names = vec![path.clone()];
+        let index = update_index(TrigramIndex::default(), file_names.clone(), 1).index;
+
+        let options = PatternOptions {
+            multiline: true,
+            ..PatternOptions::default()
+        };
+        let query = Query::from(Pattern::with_options(&[r"foo\nbar"], &options).unwrap());
+        assert_eq!(
+            index.candidates(&query, &file_names),
+            Some(file_names.clone())
+        );
+        let query = Query::from(Pattern::with_options(&[r"foo\nbaz"], &options).unwrap());
+        assert_eq!(index.candidates(&query, &file_names), Some(vec![]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
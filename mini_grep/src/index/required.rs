@@ -0,0 +1,376 @@
+use crate::fuzzy::Fuzzy;
+use crate::pattern::Pattern;
+use regex::Regex;
+use regex_syntax::hir::{Class, Hir, HirKind};
+use regex_syntax::ParserBuilder;
+
+/// Three bytes packed into the low bits, with ASCII letters lowercased.
+pub(crate) type Trigram = u32;
+
+/// Alternatives and classes with more strings than this are no longer spelled out,
+/// only the trigrams each of them needs.
+const MAX_EXACT_STRINGS: usize = 16;
+
+/// Which trigrams a file must hold for a line of it to match a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Required {
+    /// Nothing is known, so every file may match.
+    Anything,
+    Trigram(Trigram),
+    All(Vec<Required>),
+    AnyOf(Vec<Required>),
+}
+
+/// What a part of an expression may match: either every string it matches,
+/// as long as there are few of them, or only what they require.
+enum Info {
+    Exact(Vec<Vec<u8>>),
+    Inexact(Required),
+}
+
+pub(crate) fn lowercase(byte: u8) -> u8 {
+    byte.to_ascii_lowercase()
+}
+
+pub(crate) fn trigram(bytes: [u8; 3]) -> Trigram {
+    (lowercase(bytes[0]) as u32) << 16
+        | (lowercase(bytes[1]) as u32) << 8
+        | lowercase(bytes[2]) as u32
+}
+
+impl Required {
+    /// The trigrams every line matching `pattern` holds, which lets a search skip the
+    /// files that lack them.
+    pub(crate) fn of(pattern: &Pattern) -> Required {
+        let required = match pattern {
+            Pattern::Literal(text) => exact_required(&[text.as_bytes().to_vec()]),
+            Pattern::Literals { literals, .. } => exact_required(
+                &literals
+                    .iter()
+                    .map(|literal| literal.as_bytes().to_vec())
+                    .collect::<Vec<_>>(),
+            ),
+            Pattern::Regex(regex) | Pattern::Alternatives { regex, .. } => of_regex(regex),
+            Pattern::Fuzzy(fuzzy) => of_fuzzy(fuzzy),
+        };
+        // Multiline searches match a newline wherever a line ends, though the file may
+        // hold `\r\n` there instead
+        required.without_newlines()
+    }
+
+    fn without_newlines(self) -> Required {
+        match self {
+            Required::Trigram(trigram) if trigram.to_be_bytes()[1..].contains(&b'\n') => {
+                Required::Anything
+            }
+            Required::All(all) => {
+                Required::all(all.into_iter().map(Required::without_newlines).collect())
+            }
+            Required::AnyOf(any) => {
+                Required::any_of(any.into_iter().map(Required::without_newlines).collect())
+            }
+            required => required,
+        }
+    }
+
+    /// Whether a file holding these sorted trigrams may have a matching line.
+    pub(crate) fn holds(&self, trigrams: &[Trigram]) -> bool {
+        match self {
+            Required::Anything => true,
+            Required::Trigram(trigram) => trigrams.binary_search(trigram).is_ok(),
+            Required::All(all) => all.iter().all(|required| required.holds(trigrams)),
+            Required::AnyOf(any) => any.iter().any(|required| required.holds(trigrams)),
+        }
+    }
+
+    fn all(parts: Vec<Required>) -> Required {
+        let mut parts = parts
+            .into_iter()
+            .filter(|part| *part != Required::Anything)
+            .collect::<Vec<_>>();
+        match parts.len() {
+            0 => Required::Anything,
+            1 => parts.remove(0),
+            _ => Required::All(parts),
+        }
+    }
+
+    fn any_of(parts: Vec<Required>) -> Required {
+        if parts.is_empty() || parts.contains(&Required::Anything) {
+            return Required::Anything;
+        }
+        if parts.len() == 1 {
+            return parts.into_iter().next().unwrap();
+        }
+        Required::AnyOf(parts)
+    }
+}
+
+/// Patterns are parsed as if they ignored case, which is always safe since the index
+/// lowercases letters too. It also covers the letters that have non-ASCII forms under
+/// Unicode case folding, like `k` and the Kelvin sign, when the pattern ignores case.
+fn of_regex(regex: &Regex) -> Required {
+    let parsed = ParserBuilder::new()
+        .case_insensitive(true)
+        .build()
+        .parse(regex.as_str());
+    match parsed {
+        Ok(hir) => info(&hir).into_required(),
+        Err(_) => Required::Anything,
+    }
+}
+
+/// A part within `max_edits` of an expression holds at least one of `max_edits + 1`
+/// pieces of it unchanged, since every edit changes a piece at most. Only the ASCII
+/// case is folded like the index does, so other letters tell nothing when case is ignored.
+fn of_fuzzy(fuzzy: &Fuzzy) -> Required {
+    Required::any_of(
+        fuzzy
+            .texts()
+            .map(|text| {
+                if fuzzy.case_insensitive() && !text.is_ascii() {
+                    return Required::Anything;
+                }
+                let chars = text.chars().collect::<Vec<_>>();
+                let pieces = fuzzy.max_edits + 1;
+                let piece_length = chars.len() / pieces;
+                if piece_length == 0 {
+                    return Required::Anything;
+                }
+                exact_required(
+                    &(0..pieces)
+                        .map(|piece| {
+                            chars[piece * piece_length..(piece + 1) * piece_length]
+                                .iter()
+                                .collect::<String>()
+                                .into_bytes()
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect(),
+    )
+}
+
+impl Info {
+    fn into_required(self) -> Required {
+        match self {
+            Info::Exact(strings) => exact_required(&strings),
+            Info::Inexact(required) => required,
+        }
+    }
+}
+
+/// A line matching one of `strings` holds every trigram of that string.
+fn exact_required(strings: &[Vec<u8>]) -> Required {
+    Required::any_of(
+        strings
+            .iter()
+            .map(|string| {
+                if string.len() < 3 {
+                    return Required::Anything;
+                }
+                let mut trigrams = string
+                    .windows(3)
+                    .map(|window| trigram([window[0], window[1], window[2]]))
+                    .collect::<Vec<_>>();
+                trigrams.sort_unstable();
+                trigrams.dedup();
+                Required::all(trigrams.into_iter().map(Required::Trigram).collect())
+            })
+            .collect(),
+    )
+}
+
+fn info(hir: &Hir) -> Info {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => Info::Exact(vec![vec![]]),
+        HirKind::Literal(literal) => Info::Exact(vec![literal
+            .0
+            .iter()
+            .map(|&byte| lowercase(byte))
+            .collect()]),
+        HirKind::Class(class) => class_info(class),
+        HirKind::Capture(capture) => info(&capture.sub),
+        HirKind::Repetition(repetition) => match (repetition.min, repetition.max) {
+            (1, Some(1)) => info(&repetition.sub),
+            (0, Some(1)) => match info(&repetition.sub) {
+                Info::Exact(mut strings) if strings.len() < MAX_EXACT_STRINGS => {
+                    strings.push(vec![]);
+                    Info::Exact(strings)
+                }
+                _ => Info::Inexact(Required::Anything),
+            },
+            (0, _) => Info::Inexact(Required::Anything),
+            _ => Info::Inexact(info(&repetition.sub).into_required()),
+        },
+        HirKind::Concat(parts) => concat_info(parts),
+        HirKind::Alternation(alternatives) => {
+            let infos = alternatives.iter().map(info).collect::<Vec<_>>();
+            let exact_strings = infos
+                .iter()
+                .map(|info| match info {
+                    Info::Exact(strings) => Some(strings.len()),
+                    Info::Inexact(_) => None,
+                })
+                .sum::<Option<usize>>();
+
+            match exact_strings {
+                Some(amount) if amount <= MAX_EXACT_STRINGS => Info::Exact(dedup(
+                    infos
+                        .into_iter()
+                        .flat_map(|info| match info {
+                            Info::Exact(strings) => strings,
+                            Info::Inexact(_) => vec![],
+                        })
+                        .collect(),
+                )),
+                _ => Info::Inexact(Required::any_of(
+                    infos.into_iter().map(Info::into_required).collect(),
+                )),
+            }
+        }
+    }
+}
+
+/// Strings of consecutive parts are joined while there are few of them, so trigrams
+/// that cross from one part to the next are required too.
+fn concat_info(parts: &[Hir]) -> Info {
+    let mut current = vec![vec![]];
+    let mut required = vec![];
+    let mut joined_all = true;
+
+    for part in parts {
+        match info(part) {
+            Info::Exact(strings) if strings.len() * current.len() <= MAX_EXACT_STRINGS => {
+                current = dedup(
+                    current
+                        .iter()
+                        .flat_map(|prefix| {
+                            strings
+                                .iter()
+                                .map(move |string| [&prefix[..], string].concat())
+                        })
+                        .collect(),
+                );
+            }
+            Info::Exact(strings) => {
+                required.push(exact_required(&current));
+                current = strings;
+                joined_all = false;
+            }
+            Info::Inexact(part_required) => {
+                required.push(exact_required(&current));
+                required.push(part_required);
+                current = vec![vec![]];
+                joined_all = false;
+            }
+        }
+    }
+
+    if joined_all {
+        return Info::Exact(current);
+    }
+    required.push(exact_required(&current));
+    Info::Inexact(Required::all(required))
+}
+
+/// Classes of a few characters that are the same once lowercased, like `[Aa]`,
+/// are spelled out. Bigger ones may hold any trigram.
+fn class_info(class: &Class) -> Info {
+    let mut strings = vec![];
+    match class {
+        Class::Unicode(class) => {
+            for range in class.ranges() {
+                for c in range.start()..=range.end() {
+                    if strings.len() > MAX_EXACT_STRINGS {
+                        return Info::Inexact(Required::Anything);
+                    }
+                    let mut encoded = [0; 4];
+                    let mut string = c.encode_utf8(&mut encoded).as_bytes().to_vec();
+                    string.iter_mut().for_each(|byte| *byte = lowercase(*byte));
+                    strings.push(string);
+                    strings.dedup();
+                }
+            }
+        }
+        Class::Bytes(class) => {
+            for range in class.ranges() {
+                for byte in range.start()..=range.end() {
+                    if strings.len() > MAX_EXACT_STRINGS {
+                        return Info::Inexact(Required::Anything);
+                    }
+                    strings.push(vec![lowercase(byte)]);
+                    strings.dedup();
+                }
+            }
+        }
+    }
+    let strings = dedup(strings);
+    if strings.len() > MAX_EXACT_STRINGS {
+        return Info::Inexact(Required::Anything);
+    }
+    Info::Exact(strings)
+}
+
+fn dedup(mut strings: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    strings.sort_unstable();
+    strings.dedup();
+    strings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PatternOptions;
+
+    fn trigrams(text: &str) -> Vec<Trigram> {
+        let mut trigrams = text
+            .as_bytes()
+            .windows(3)
+            .map(|window| trigram([window[0], window[1], window[2]]))
+            .collect::<Vec<_>>();
+        trigrams.sort_unstable();
+        trigrams
+    }
+
+    fn may_match(expressions: &[&str], case_insensitive: bool, text: &str) -> bool {
+        let options = PatternOptions {
+            case_insensitive,
+            whole_word: false,
+            fuzzy: None,
+            multiline: false,
+        };
+        let pattern = Pattern::with_options(expressions, &options).unwrap();
+        Required::of(&pattern).holds(&trigrams(text))
+    }
+
+    #[test]
+    fn only_files_with_every_literal_trigram_may_match() {
+        assert!(may_match(&["hello"], false, "say hello there"));
+        assert!(!may_match(&["hello"], false, "say help"));
+        assert!(may_match(&["HeLLo"], true, "HELLO"));
+        assert!(may_match(&[r"fo[ox]\d+bar"], false, "fox and bar"));
+        assert!(!may_match(&[r"fo[ox]\d+bar"], false, "fob and bar"));
+        assert!(may_match(&["apple|pear"], false, "a pear"));
+        assert!(!may_match(&["apple", "pear"], true, "a plum"));
+        assert!(may_match(&["colou?r"], false, "color"));
+
+        let fuzzy = |text: &str| {
+            let options = PatternOptions {
+                fuzzy: Some(1),
+                ..PatternOptions::default()
+            };
+            let pattern = Pattern::with_options(&["database"], &options).unwrap();
+            Required::of(&pattern).holds(&trigrams(text))
+        };
+        assert!(fuzzy("the datbase is down"));
+        assert!(fuzzy("dxtabase"));
+        assert!(!fuzzy("the dxtxbxse is down"));
+
+        let unknown = |expression| Required::of(&Pattern::regex(expression).unwrap());
+        assert_eq!(unknown(r"\w+"), Required::Anything);
+        assert_eq!(unknown("a.b"), Required::Anything);
+        assert_eq!(unknown("hello|.*"), Required::Anything);
+    }
+}
@@ -1,117 +1,672 @@
+use cancel::PieceLimit;
+use filter::LineFilter;
+use lines::{for_each_chunk, open_numbered_lines, Chunk, NumberedLines};
+use multiline::{ScannedChunk, Seams};
+use pooling::{Spawn, WorkerPool};
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
 use std::thread::JoinHandle;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    thread,
+use stealing::StealingPool;
+use stream::{collect_outcomes, Outcome};
+
+pub use auto::{choose_strategy, grep_auto, StrategyChoice, Workload};
+pub use cancel::CancelToken;
+pub use config::{PatternKind, SearchConfig};
+pub use context::{context_groups, ContextGroup, ContextGrouper, GroupLine};
+pub use error::GrepError;
+pub use follow::{grep_follow, FollowEvent, FollowStream};
+pub use fuzzy::Fuzzy;
+pub use index::{update_index, IndexUpdate, TrigramIndex};
+pub use matches::{ContextLine, Match, Span};
+pub use multiline::DEFAULT_MULTILINE_LINES;
+pub use pattern::{Pattern, PatternOptions};
+pub use pooling::default_thread_amount;
+pub use query::{Query, SearchOptions};
+pub use ranges::grep_ranges;
+pub use reader::{search_reader, stream_reader, stream_reader_cancellable};
+pub use replace::{replace, FileChanges, LineChange, ReplaceResults, Replacement};
+pub use results::SearchResults;
+pub use searcher::{
+    Chunked, PerFile, Pool, Ranges, Searcher, Sequential, WorkStealing, DEFAULT_CHUNK_SIZE,
 };
+pub use stealing::{SchedulerReport, WorkerReport};
+pub use stream::{Delivery, MatchStream, SearchEvent, SearchEvents};
+pub use walk::{walk, WalkOptions, WalkResult};
 
-const CHUNK_SIZE: usize = 10_000;
+mod auto;
+mod cancel;
+mod config;
+mod context;
+mod encoding;
+mod error;
+mod filter;
+mod follow;
+mod fuzzy;
+mod gzip;
+mod index;
+mod lines;
+mod matches;
+mod multiline;
+mod pattern;
+mod pooling;
+mod query;
+mod ranges;
+mod reader;
+mod replace;
+mod results;
+mod searcher;
+mod stealing;
+mod stream;
+mod walk;
 
-pub fn grep_seq(pattern: String, file_names: Vec<String>) -> Vec<String> {
-    file_names
-        .into_iter()
-        .map(|file_name| filter_lines_from_file(file_name, pattern.clone()))
-        .flatten()
-        .collect::<Vec<_>>()
+/// How a search splits its work between threads, chosen at runtime. Each variant
+/// searches like the `Searcher` of the same name, with the default chunk size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// One file after another, on a single thread.
+    Sequential,
+    /// One thread per file.
+    PerFile,
+    /// One thread per file, plus one per chunk of lines of that file.
+    Chunked,
+    /// One thread per file, plus one per CPU reading and scanning byte ranges of that file.
+    Ranges,
+    /// Files and chunks of lines taken by a fixed amount of threads.
+    Pool { thread_amount: usize },
+    /// Files and chunks of lines queued on the deque of the worker that read them,
+    /// where idle workers steal them from.
+    Stealing { thread_amount: usize },
 }
 
-pub fn grep_conc(pattern: String, file_names: Vec<String>) -> Vec<String> {
-    let threads: Vec<JoinHandle<Vec<String>>> = file_names.into_iter()
-        .map(|file| {
-            let pattern_clone = pattern.clone();
-            thread::spawn(|| filter_lines_from_file(file, pattern_clone).collect::<Vec<_>>())
-        })
-        .collect();
+pub fn grep_seq(query: impl Into<Query>, file_names: Vec<String>) -> SearchResults {
+    search(Strategy::Sequential, query, file_names)
+}
 
-    threads
-        .into_iter().map(|t| t.join().unwrap())
-        .flatten()
-        .collect::<Vec<_>>()
+pub fn grep_conc(query: impl Into<Query>, file_names: Vec<String>) -> SearchResults {
+    search(Strategy::PerFile, query, file_names)
 }
 
-pub fn grep_chunk(pattern: String, file_names: Vec<String>) -> Vec<String> {
-    let file_threads = file_names
-        .into_iter()
-        .map(|file_name| spawn_file_thread(file_name, CHUNK_SIZE, pattern.clone()));
+pub fn grep_chunk(query: impl Into<Query>, file_names: Vec<String>) -> SearchResults {
+    search(Strategy::Chunked, query, file_names)
+}
 
-    file_threads
-        .into_iter()
-        .map(|t| t.join().unwrap())
-        .flatten()
-        .collect()
+/// Runs every file and every chunk on a fixed amount of threads. Files are read by
+/// pool tasks that queue their chunks on the same pool, so free workers pick up
+/// whatever is pending, regardless of which file it belongs to.
+pub fn grep_pool(
+    query: impl Into<Query>,
+    file_names: Vec<String>,
+    thread_amount: usize,
+) -> SearchResults {
+    search(Strategy::Pool { thread_amount }, query, file_names)
+}
+
+/// Starts a search in the background and hands out its matches while it runs,
+/// instead of waiting for every file to be over.
+pub fn grep_stream(
+    searcher: impl Searcher + 'static,
+    query: impl Into<Query>,
+    file_names: Vec<String>,
+    delivery: Delivery,
+) -> MatchStream {
+    grep_stream_cancellable(searcher, query, file_names, delivery, &CancelToken::new())
 }
 
-fn filter_lines_from_file(file_name: String, pattern: String) -> impl Iterator<Item = String> {
-    BufReader::new(File::open(file_name).unwrap())
-        .lines()
-        .map(|line| line.unwrap())
-        .filter(move |line| line.contains(&pattern))
+/// Like `grep_stream`, stopping once `cancel` is cancelled. The stream hands out
+/// what was found until then, and files that were not over end with an error.
+pub fn grep_stream_cancellable(
+    searcher: impl Searcher + 'static,
+    query: impl Into<Query>,
+    file_names: Vec<String>,
+    delivery: Delivery,
+    cancel: &CancelToken,
+) -> MatchStream {
+    stream_files(
+        Arc::new(searcher),
+        Arc::new(query.into()),
+        file_names,
+        DEFAULT_CHUNK_SIZE,
+        None,
+        delivery,
+        cancel,
+    )
 }
 
-fn spawn_file_thread(
-    file_name: String,
+/// Searches every file with the given searcher, returning once all of them are over.
+pub fn search(
+    searcher: impl Searcher,
+    query: impl Into<Query>,
+    file_names: Vec<String>,
+) -> SearchResults {
+    search_cancellable(searcher, query, file_names, &CancelToken::new())
+}
+
+/// Like `search`, returning early once `cancel` is cancelled, possibly from another
+/// thread. Files that were not over by then report `GrepError::Cancelled`.
+pub fn search_cancellable(
+    searcher: impl Searcher,
+    query: impl Into<Query>,
+    file_names: Vec<String>,
+    cancel: &CancelToken,
+) -> SearchResults {
+    search_files(
+        &searcher,
+        Arc::new(query.into()),
+        file_names,
+        DEFAULT_CHUNK_SIZE,
+        None,
+        cancel,
+    )
+}
+
+fn search_files(
+    searcher: &dyn Searcher,
+    query: Arc<Query>,
+    file_names: Vec<String>,
     chunk_size: usize,
-    pattern: String,
-) -> JoinHandle<impl Iterator<Item = String>> {
-    thread::spawn(move || {
-        let chunk_threads = split_file_into_chunk_threads(file_name, chunk_size.clone(), pattern);
+    thread_amount: Option<usize>,
+    cancel: &CancelToken,
+) -> SearchResults {
+    let file_names: Vec<Arc<str>> = file_names.into_iter().map(Arc::from).collect();
+    let (tx, rx) = channel::<Outcome>();
 
-        chunk_threads
-            .into_iter()
-            .map(|t| t.join().unwrap())
-            .flatten()
-    })
+    let job = Job::new(query.clone(), tx, cancel.clone(), chunk_size, thread_amount);
+    searcher.scan(file_names.clone(), job);
+
+    collect_outcomes(&file_names, rx, &query)
 }
 
-fn split_file_into_chunk_threads(
-    file_name: String,
+fn stream_files(
+    searcher: Arc<dyn Searcher>,
+    query: Arc<Query>,
+    file_names: Vec<String>,
     chunk_size: usize,
-    pattern: String,
-) -> Vec<JoinHandle<Vec<String>>> {
-    let mut chunk_threads: Vec<JoinHandle<Vec<String>>> = vec![];
-    let mut br = BufReader::new(File::open(file_name).unwrap()).lines();
-
-    loop {
-        let chunk: Vec<String> = br
-            .by_ref()
-            .take(chunk_size)
-            .map(|line| line.unwrap())
-            .collect::<Vec<_>>();
+    thread_amount: Option<usize>,
+    delivery: Delivery,
+    cancel: &CancelToken,
+) -> MatchStream {
+    let file_names: Vec<Arc<str>> = file_names.into_iter().map(Arc::from).collect();
+    let (tx, rx) = channel::<Outcome>();
+    // Dropping the stream only cancels its own search, not whatever else `cancel` stops
+    let cancel = cancel.child();
+
+    let job = Job::new(query.clone(), tx, cancel.clone(), chunk_size, thread_amount);
+    let file_names_clone = file_names.clone();
+    let search = thread::spawn(move || searcher.scan(file_names_clone, job));
+
+    MatchStream::new(file_names, &query, delivery, rx, search, cancel)
+}
+
+/// What every worker of a search shares: what to look for, where to report it,
+/// and whether to stop. Searchers hand it out to the threads they start.
+///
+/// Every file of a search is reported as done exactly once, after its pieces:
+/// the scanning methods do it on their own, and searchers that scan files some
+/// other way call `report_piece` and `report_done` themselves.
+#[derive(Clone)]
+pub struct Job {
+    query: Arc<Query>,
+    outcomes: Sender<Outcome>,
+    cancel: CancelToken,
+    chunk_size: usize,
+    thread_amount: Option<usize>,
+}
+
+impl Job {
+    fn new(
+        query: Arc<Query>,
+        outcomes: Sender<Outcome>,
+        cancel: CancelToken,
+        chunk_size: usize,
+        thread_amount: Option<usize>,
+    ) -> Job {
+        Job {
+            query,
+            outcomes,
+            cancel,
+            chunk_size,
+            thread_amount,
+        }
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// Lines in each chunk, for searchers that split files into chunks.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Threads the search was asked to run on, for searchers that start a fixed
+    /// amount of them, in place of their own amount.
+    pub fn thread_amount(&self) -> Option<usize> {
+        self.thread_amount
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Scans file number `file` whole, on the calling thread.
+    pub fn scan_file(&self, file: usize, file_name: Arc<str>) {
+        scan_file(file, file_name, self.clone());
+    }
+
+    /// Scans file number `file` in chunks of lines, each one on a thread of its own.
+    /// Returns once every chunk is over.
+    pub fn scan_file_chunks(&self, file: usize, file_name: Arc<str>) {
+        scan_file_chunks(file, file_name, self.clone());
+    }
+
+    /// Reports the matches of piece number `piece` of a file. Pieces are numbered
+    /// from 0 in file order, and may be reported in any order.
+    pub fn report_piece(&self, file: usize, piece: usize, matches: Vec<Match>) {
+        let _ = self.outcomes.send(Outcome::Piece {
+            file,
+            piece,
+            matches,
+        });
+    }
+
+    /// Reports that a file is over after `pieces` pieces, or failed.
+    /// A file that was cut short because the search was cancelled did not fail on its own,
+    /// but is not over either.
+    pub fn report_done(
+        &self,
+        file: usize,
+        file_name: &str,
+        pieces: usize,
+        result: Result<(), GrepError>,
+    ) {
+        let result = match result {
+            Ok(()) if self.cancel.is_cancelled() => {
+                Err(GrepError::Cancelled(file_name.to_string()))
+            }
+            result => result,
+        };
+        let _ = self.outcomes.send(Outcome::FileDone {
+            file,
+            pieces,
+            result,
+        });
+    }
+
+    /// Reports how busy the workers of a work-stealing search were, once every file
+    /// is over.
+    pub fn report_scheduler(&self, report: SchedulerReport) {
+        let _ = self.outcomes.send(Outcome::Scheduled(report));
+    }
+}
+
+/// Runs `scan` for every file on a thread of its own and waits for all of them.
+/// A thread that panics never reports its file as done, which is what tells it apart.
+fn spawn_file_threads(file_names: Vec<Arc<str>>, job: Job, scan: fn(usize, Arc<str>, Job)) {
+    let file_threads: Vec<JoinHandle<()>> = file_names
+        .into_iter()
+        .enumerate()
+        .map(|(file, file_name)| {
+            let job = job.clone();
+            thread::spawn(move || scan(file, file_name, job))
+        })
+        .collect();
+
+    file_threads.into_iter().for_each(|t| {
+        let _ = t.join();
+    });
+}
+
+/// Scans a whole file with a single filter, reporting its matches as soon as
+/// their context is complete.
+fn scan_file(file: usize, file_name: Arc<str>, job: Job) {
+    let lines = open_numbered_lines(&file_name, job.query.options.decompress);
+    scan_lines(file, &file_name, lines, &job);
+}
+
+/// Like `scan_file`, for lines that may come from anything that can be read.
+fn scan_lines<R: BufRead>(
+    file: usize,
+    file_name: &str,
+    lines: Result<NumberedLines<R>, GrepError>,
+    job: &Job,
+) {
+    let mut pieces = 0;
+    let result = lines.and_then(|lines| {
+        let on_matches = |matches| {
+            job.report_piece(file, pieces, matches);
+            pieces += 1;
+        };
+        match job.query.options.multiline {
+            Some(_) => filter_joined_lines(file_name, lines, job, on_matches),
+            None => filter_lines(file_name, lines, &job.query, &job.cancel, on_matches),
+        }
+    });
+
+    job.report_done(file, file_name, pieces, result);
+}
+
+fn filter_lines<R: BufRead>(
+    file_name: &str,
+    lines: NumberedLines<R>,
+    query: &Query,
+    cancel: &CancelToken,
+    mut on_matches: impl FnMut(Vec<Match>),
+) -> Result<(), GrepError> {
+    let mut filter = LineFilter::new(file_name, query, lines.is_binary());
 
-        if chunk.is_empty() {
+    for line in lines {
+        if cancel.is_cancelled() {
             break;
+        }
+        let line = line?;
+        let keep_reading = filter.push(line.number, line.offset, &line.text);
+
+        let ready = filter.take_ready();
+        if !ready.is_empty() {
+            on_matches(ready);
+        }
+        if !keep_reading {
+            break;
+        }
+    }
+
+    let last = filter.finish();
+    if !last.is_empty() {
+        on_matches(last);
+    }
+    Ok(())
+}
+
+/// Like `filter_lines`, for matches that may span lines, which are looked for in
+/// chunks one after another, since each needs lines of the ones after it anyway.
+fn filter_joined_lines<R: BufRead>(
+    file_name: &str,
+    lines: NumberedLines<R>,
+    job: &Job,
+    mut on_matches: impl FnMut(Vec<Match>),
+) -> Result<(), GrepError> {
+    let chunks = FileChunks::new(job);
+    let mut pieces = 0;
+
+    for_each_chunk(
+        lines,
+        job.chunk_size,
+        job.query.overlap(),
+        chunks.limit.stop(),
+        |chunk| {
+            if chunks.limit.stop().is_cancelled() {
+                return;
+            }
+            let scanned = multiline::scan_chunk(chunk, &job.query, chunks.limit.stop());
+            for (_, matches) in chunks.piece_scanned(pieces, scanned, file_name, job) {
+                if !matches.is_empty() {
+                    on_matches(matches);
+                }
+            }
+            pieces += 1;
+        },
+    )
+}
+
+/// What the chunks of a file share while each is scanned on its own.
+struct FileChunks {
+    limit: PieceLimit,
+    seams: Seams,
+    /// Chunks queued on a pool and not scanned yet.
+    queued: AtomicUsize,
+}
+
+impl FileChunks {
+    fn new(job: &Job) -> FileChunks {
+        FileChunks {
+            limit: PieceLimit::new(&job.query, job.cancel.child()),
+            seams: Seams::default(),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Scans chunk number `piece` and reports its matches. Those of multiline searches
+    /// are reported once the chunks before it are, along with any after it that were
+    /// waiting for it.
+    fn scan(&self, file: usize, piece: usize, chunk: Chunk, file_name: &str, job: &Job) {
+        let stop = self.limit.stop();
+        let over = match job.query.options.multiline {
+            Some(_) => {
+                let scanned = multiline::scan_chunk(chunk, &job.query, stop);
+                self.piece_scanned(piece, scanned, file_name, job)
+            }
+            None => {
+                let matches = filter_chunk(chunk, file_name, &job.query, stop);
+                self.limit.piece_done(piece, matches.len());
+                vec![(piece, matches)]
+            }
         };
+        for (piece, matches) in over {
+            job.report_piece(file, piece, matches);
+        }
+    }
 
-        add_new_chunk_thread(chunk, &mut chunk_threads, pattern.clone());
+    fn piece_scanned(
+        &self,
+        piece: usize,
+        scanned: ScannedChunk,
+        file_name: &str,
+        job: &Job,
+    ) -> Vec<(usize, Vec<Match>)> {
+        let over =
+            self.seams
+                .piece_scanned(piece, scanned, file_name, &job.query, self.limit.stop());
+        for (piece, matches) in &over {
+            self.limit.piece_done(*piece, matches.len());
+        }
+        over
     }
+}
 
-    chunk_threads
+fn scan_file_chunks(file: usize, file_name: Arc<str>, job: Job) {
+    let lines = open_numbered_lines(&file_name, job.query.options.decompress);
+    scan_chunks(file, file_name, lines, job);
 }
 
-fn add_new_chunk_thread(
-    chunk: Vec<String>,
-    chunk_threads: &mut Vec<JoinHandle<Vec<String>>>,
-    pattern: String,
+/// Hands every chunk to a thread of its own as soon as it is read, so chunks are
+/// scanned while the following ones are still being read. With a thread amount,
+/// reading waits for the oldest chunk once that many are being scanned. Once the
+/// chunks at the start of the file hold as many matches as its limit, the rest are
+/// stopped.
+fn scan_chunks<R: BufRead>(
+    file: usize,
+    file_name: Arc<str>,
+    lines: Result<NumberedLines<R>, GrepError>,
+    job: Job,
 ) {
-    let filtered_lines: JoinHandle<Vec<String>> = thread::spawn(move || {
-        chunk
-            .into_iter()
-            .filter(move |line| line.contains(&pattern))
-            .collect()
+    let mut chunk_threads: VecDeque<JoinHandle<()>> = VecDeque::new();
+    let mut pieces = 0;
+    let chunks = Arc::new(FileChunks::new(&job));
+
+    // Lines keep the number and offset they had in the whole file,
+    // so chunk threads report global positions without further bookkeeping.
+    let result = lines.and_then(|lines| {
+        for_each_chunk(
+            lines,
+            job.chunk_size,
+            job.query.overlap(),
+            chunks.limit.stop(),
+            |chunk| {
+                if job
+                    .thread_amount
+                    .is_some_and(|amount| chunk_threads.len() >= amount)
+                {
+                    let _ = chunk_threads.pop_front().map(JoinHandle::join);
+                }
+                let piece = pieces;
+                pieces += 1;
+                let (file_name, job, chunks) = (file_name.clone(), job.clone(), chunks.clone());
+                chunk_threads.push_back(thread::spawn(move || {
+                    chunks.scan(file, piece, chunk, &file_name, &job);
+                }));
+            },
+        )
+    });
+
+    job.report_done(file, &file_name, pieces, result);
+
+    // Chunks already handed out are joined even if reading failed later on,
+    // so no worker outlives the search.
+    chunk_threads.into_iter().for_each(|t| {
+        let _ = t.join();
     });
+}
+
+/// Scans the lines of a chunk, using the lines around it only as context,
+/// since those are reported by the neighbouring chunks. A stopped chunk reports
+/// what it found so far, which is either past the limit or discarded.
+fn filter_chunk(chunk: Chunk, file_name: &str, query: &Query, stop: &CancelToken) -> Vec<Match> {
+    let mut filter = LineFilter::new(file_name, query, chunk.binary);
 
-    chunk_threads.push(filtered_lines);
+    for line in chunk.leading {
+        filter.push_context(line.number, line.offset, &line.text);
+    }
+    for line in chunk.lines {
+        if stop.is_cancelled() || !filter.push(line.number, line.offset, &line.text) {
+            return filter.finish();
+        }
+    }
+    for line in chunk.trailing {
+        if !filter.wants_context() {
+            break;
+        }
+        filter.push_context(line.number, line.offset, &line.text);
+    }
+
+    filter.finish()
+}
+
+fn run_pool(file_names: Vec<Arc<str>>, job: Job, thread_amount: usize) {
+    let pool = WorkerPool::new(thread_amount);
+
+    for (file, file_name) in file_names.into_iter().enumerate() {
+        let (spawner, job) = (pool.spawner(), job.clone());
+        pool.spawn(move || {
+            let lines = open_numbered_lines(&file_name, job.query.options.decompress);
+            queue_chunk_tasks(file, file_name, lines, spawner, thread_amount, job)
+        });
+    }
+
+    pool.join();
+}
+
+/// Reads every file on a work-stealing pool. The chunks of a file are queued on the
+/// worker reading it, so other workers only take them once they run out of their own.
+/// How busy they were is reported to `job` as well.
+fn run_stealing(file_names: Vec<Arc<str>>, job: Job, thread_amount: usize) -> SchedulerReport {
+    let pool = StealingPool::new(thread_amount);
+
+    for (file, file_name) in file_names.into_iter().enumerate() {
+        let (spawner, job) = (pool.spawner(), job.clone());
+        pool.spawn(move || {
+            let lines = open_numbered_lines(&file_name, job.query.options.decompress);
+            queue_chunk_tasks(file, file_name, lines, spawner, thread_amount, job)
+        });
+    }
+
+    let report = pool.join();
+    job.report_scheduler(report.clone());
+    report
+}
+
+/// Counts a chunk among the queued ones of its file until it is dropped, along with
+/// the task it was moved into, even if that panicked or never ran.
+struct QueuedChunk(Arc<FileChunks>);
+
+impl QueuedChunk {
+    fn new(chunks: Arc<FileChunks>) -> QueuedChunk {
+        chunks.queued.fetch_add(1, Ordering::Relaxed);
+        QueuedChunk(chunks)
+    }
+}
+
+impl Drop for QueuedChunk {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Queues the chunks of a file on a pool of `thread_amount` workers. Once twice as
+/// many as there are workers wait, the reading thread scans the next one itself
+/// rather than reading further ahead. It cannot wait for the pool instead, since it
+/// may well be one of its workers.
+fn queue_chunk_tasks<R: BufRead>(
+    file: usize,
+    file_name: Arc<str>,
+    lines: Result<NumberedLines<R>, GrepError>,
+    spawner: impl Spawn,
+    thread_amount: usize,
+    job: Job,
+) {
+    let mut pieces = 0;
+    let chunks = Arc::new(FileChunks::new(&job));
+
+    let result = lines.and_then(|lines| {
+        for_each_chunk(
+            lines,
+            job.chunk_size,
+            job.query.overlap(),
+            chunks.limit.stop(),
+            |chunk| {
+                let piece = pieces;
+                pieces += 1;
+                if chunks.queued.load(Ordering::Relaxed) >= 2 * thread_amount {
+                    chunks.scan(file, piece, chunk, &file_name, &job);
+                    return;
+                }
+                let queued = QueuedChunk::new(chunks.clone());
+                let (file_name, job) = (file_name.clone(), job.clone());
+                spawner.spawn(move || {
+                    queued.0.scan(file, piece, chunk, &file_name, &job);
+                });
+            },
+        )
+    });
+
+    job.report_done(file, &file_name, pieces, result);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Every strategy, with pools of two threads, for tests that must hold in all of them.
+    pub(crate) const STRATEGIES: [Strategy; 6] = [
+        Strategy::Sequential,
+        Strategy::PerFile,
+        Strategy::Chunked,
+        Strategy::Ranges,
+        Strategy::Pool { thread_amount: 2 },
+        Strategy::Stealing { thread_amount: 2 },
+    ];
+
+    fn search_in_chunks(file_name: &str, chunk_size: usize, query: Query) -> SearchResults {
+        let (tx, rx) = channel();
+        let query = Arc::new(query);
+        let job = Job::new(query.clone(), tx, CancelToken::new(), chunk_size, None);
+        scan_file_chunks(0, file_name.into(), job);
+        collect_outcomes(&[file_name.into()], rx, &query)
+    }
+
+    fn lines(result: SearchResults) -> Vec<String> {
+        assert!(!result.has_errors(), "{:?}", result.errors);
+        result.matches.into_iter().map(|found| found.line).collect()
+    }
+
     #[test]
     fn single_file() {
         let result = grep_seq("test".to_string(), vec!["resources/test1.txt".to_string()]);
         assert_eq!(
-            result,
+            lines(result),
             vec![
                 "This is a test file".to_string(),
                 "It's a file that's for testing".to_string()
@@ -123,7 +678,7 @@ mod tests {
     fn single_file_conc() {
         let result = grep_conc("test".to_string(), vec!["resources/test1.txt".to_string()]);
         assert_eq!(
-            result,
+            lines(result),
             vec![
                 "This is a test file".to_string(),
                 "It's a file that's for testing".to_string()
@@ -143,9 +698,9 @@ mod tests {
         assert_found_thread_for_both_texts(result);
     }
 
-    fn assert_found_thread_for_both_texts(result: Vec<String>) {
+    fn assert_found_thread_for_both_texts(result: SearchResults) {
         assert_eq!(
-            result,
+            lines(result),
             vec![
                 "We are multithreading!".to_string(),
                 "14:23 That I will not take from a thread even to a shoelatchet, and".to_string(),
@@ -160,6 +715,49 @@ mod tests {
         )
     }
 
+    #[test]
+    fn regex_pattern_in_every_strategy() {
+        let files = vec!["resources/test1.txt".to_string()];
+        let expected = vec![
+            "Very helpful!".to_string(),
+            "Thanks for helping, file!".to_string(),
+        ];
+
+        let pattern = || Pattern::regex(r"help(ful|ing)").unwrap();
+
+        assert_eq!(lines(grep_seq(pattern(), files.clone())), expected);
+        assert_eq!(lines(grep_conc(pattern(), files.clone())), expected);
+        assert_eq!(lines(grep_chunk(pattern(), files.clone())), expected);
+        assert_eq!(lines(grep_pool(pattern(), files.clone(), 2)), expected);
+        assert_eq!(lines(grep_ranges(pattern(), files)), expected);
+    }
+
+    #[test]
+    fn several_literals_in_every_strategy() {
+        let files = vec!["resources/test1.txt".to_string()];
+        let pattern = || {
+            Pattern::with_options(&["multi", "test", "Very"], &PatternOptions::default()).unwrap()
+        };
+
+        for result in [
+            grep_seq(pattern(), files.clone()),
+            grep_conc(pattern(), files.clone()),
+            grep_chunk(pattern(), files.clone()),
+            grep_pool(pattern(), files.clone(), 2),
+            grep_ranges(pattern(), files.clone()),
+        ] {
+            let patterns = result
+                .matches
+                .iter()
+                .map(|found| (found.line_number, found.matched_patterns()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                patterns,
+                vec![(1, vec![1]), (2, vec![1]), (3, vec![2]), (5, vec![0])]
+            );
+        }
+    }
+
     #[test]
     fn two_files_chunk() {
         let result = grep_chunk(
@@ -171,4 +769,323 @@ mod tests {
         );
         assert_found_thread_for_both_texts(result);
     }
+
+    #[test]
+    fn matches_know_their_file_line_and_spans() {
+        let result = grep_seq(
+            Pattern::regex("help(ful|ing)").unwrap(),
+            vec!["resources/test1.txt".to_string()],
+        );
+
+        assert_eq!(
+            result.matches[1],
+            Match {
+                path: "resources/test1.txt".to_string(),
+                line_number: 4,
+                byte_offset: 65,
+                line: "Thanks for helping, file!".to_string(),
+                spans: vec![Span {
+                    start: 11,
+                    end: 18,
+                    pattern: 0,
+                    edits: None
+                }],
+                before: vec![],
+                after: vec![],
+                binary: false,
+            }
+        );
+    }
+
+    #[test]
+    fn chunked_matches_keep_global_line_numbers() {
+        let result = search_in_chunks("resources/test1.txt", 2, Query::from("file"))
+            .matches
+            .into_iter()
+            .map(|found| (found.line_number, found.byte_offset))
+            .collect::<Vec<_>>();
+
+        assert_eq!(result, vec![(1, 0), (2, 20), (4, 65)]);
+    }
+
+    #[test]
+    fn chunks_share_context_lines_with_their_neighbours() {
+        let query = Query::new(
+            "i",
+            SearchOptions {
+                before_context: 2,
+                after_context: 2,
+                ..SearchOptions::default()
+            },
+        );
+        let expected = grep_seq(query.clone(), vec!["resources/test1.txt".to_string()]);
+
+        for chunk_size in [1, 2, 3] {
+            let result = search_in_chunks("resources/test1.txt", chunk_size, query.clone());
+            assert_eq!(
+                result.matches, expected.matches,
+                "chunk size {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn failing_files_are_reported_next_to_matches() {
+        let files = vec![
+            "resources/missing.txt".to_string(),
+            "resources/latin1.txt".to_string(),
+            "resources/test1.txt".to_string(),
+        ];
+
+        for result in [
+            grep_seq("file", files.clone()),
+            grep_conc("file", files.clone()),
+            grep_chunk("file", files.clone()),
+            grep_pool("file", files.clone(), 2),
+            grep_ranges("file", files.clone()),
+        ] {
+            // Latin-1 lines are transcoded instead of failing
+            assert_eq!(result.matches.len(), 4);
+            assert_eq!(result.matches[0].line, "Café au lait, file");
+            assert_eq!(result.errors.len(), 1);
+            assert!(matches!(
+                &result.errors[0],
+                GrepError::NotFound(path) if path == "resources/missing.txt"
+            ));
+        }
+    }
+
+    #[test]
+    fn compressed_files_are_searched_by_every_strategy() {
+        let query = Query::new(
+            "file",
+            SearchOptions {
+                decompress: true,
+                ..SearchOptions::default()
+            },
+        );
+        let expected = grep_seq(query.clone(), vec!["resources/test1.txt".to_string()]);
+        let files = vec!["resources/test1.txt.gz".to_string()];
+
+        for result in [
+            grep_seq(query.clone(), files.clone()),
+            grep_conc(query.clone(), files.clone()),
+            grep_chunk(query.clone(), files.clone()),
+            grep_pool(query.clone(), files.clone(), 2),
+            grep_ranges(query.clone(), files.clone()),
+        ] {
+            let found = |result: &SearchResults| {
+                result
+                    .matches
+                    .iter()
+                    .map(|found| (found.line_number, found.byte_offset, found.line.clone()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(found(&result), found(&expected));
+        }
+    }
+
+    #[test]
+    fn binary_and_utf16_files_are_searched_by_every_strategy() {
+        let dir = std::env::temp_dir().join(format!("mini_grep_encodings_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("app.bin");
+        std::fs::write(&binary, b"\x7fELF\0\x01\nfile table\n").unwrap();
+        let utf16 = dir.join("notes.txt");
+        let mut bytes = vec![0xff, 0xfe];
+        for unit in "première ligne\r\nle file d'attente\r\n".encode_utf16() {
+            bytes.extend(unit.to_le_bytes());
+        }
+        std::fs::write(&utf16, bytes).unwrap();
+        let files = [&binary, &utf16]
+            .map(|path| path.to_string_lossy().into_owned())
+            .to_vec();
+        let as_text = Query::new(
+            "file",
+            SearchOptions {
+                text: true,
+                ..SearchOptions::default()
+            },
+        );
+
+        for strategy in STRATEGIES {
+            let result = search(strategy, "file", files.clone());
+            assert!(!result.has_errors(), "{:?}", result.errors);
+            let found = result
+                .matches
+                .iter()
+                .map(|found| {
+                    (
+                        found.line_number,
+                        found.byte_offset,
+                        found.line.as_str(),
+                        found.binary,
+                    )
+                })
+                .collect::<Vec<_>>();
+            // Offsets of UTF-16 lines count its bytes, from past the byte order mark
+            assert_eq!(
+                found,
+                vec![
+                    (2, 7, "file table", true),
+                    (2, 2 + 2 * 16, "le file d'attente", false)
+                ],
+                "{:?}",
+                strategy
+            );
+
+            let result = search(strategy, as_text.clone(), files.clone());
+            assert!(result.matches.iter().all(|found| !found.binary));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cancelled_searches_report_unfinished_files() {
+        let files = vec![
+            "resources/test1.txt".to_string(),
+            "resources/missing.txt".to_string(),
+        ];
+        let cancel = CancelToken::new();
+        cancel.cancel();
+
+        for strategy in STRATEGIES {
+            let result = search_cancellable(strategy, "file", files.clone(), &cancel);
+            assert!(result.matches.is_empty(), "{:?}", strategy);
+            assert!(matches!(&result.errors[0], GrepError::Cancelled(_)));
+            assert!(matches!(&result.errors[1], GrepError::NotFound(_)));
+        }
+    }
+
+    #[test]
+    fn chunks_past_the_limit_do_not_change_what_is_found() {
+        let query = Query::new(
+            "i",
+            SearchOptions {
+                max_count: Some(2),
+                after_context: 1,
+                ..SearchOptions::default()
+            },
+        );
+        let expected = grep_seq(query.clone(), vec!["resources/test1.txt".to_string()]);
+
+        for chunk_size in [1, 2] {
+            let result = search_in_chunks("resources/test1.txt", chunk_size, query.clone());
+            assert_eq!(
+                result.matches, expected.matches,
+                "chunk size {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn pool_keeps_file_order_with_more_files_than_threads() {
+        let files = [
+            "test1.txt",
+            "missing.txt",
+            "latin1.txt",
+            "test1.txt",
+            "test1.txt",
+        ]
+        .iter()
+        .map(|file| format!("resources/{}", file))
+        .collect::<Vec<_>>();
+
+        let pooled = grep_pool("file", files.clone(), 2);
+        let sequential = grep_seq("file", files);
+
+        assert_eq!(pooled.matches, sequential.matches);
+        assert_eq!(pooled.errors.len(), 1);
+    }
+
+    #[test]
+    fn pools_scan_chunks_they_cannot_queue_while_reading() {
+        // The only worker reads the file, so every chunk past the first two is
+        // scanned by it before the queued ones
+        let files = vec!["resources/test1.txt".to_string()];
+        for multiline in [None, Some(2)] {
+            let config = SearchConfig::new("i\\w*").context(1).multiline(multiline);
+            let expected = config.clone().strategy(Strategy::Sequential);
+            let expected = expected.search(files.clone()).unwrap().matches;
+
+            for strategy in [
+                Strategy::Pool { thread_amount: 1 },
+                Strategy::Stealing { thread_amount: 1 },
+            ] {
+                let result = config
+                    .clone()
+                    .strategy(strategy)
+                    .chunk_size(1)
+                    .search(files.clone())
+                    .unwrap();
+                assert_eq!(result.matches, expected, "{:?} {:?}", strategy, multiline);
+            }
+        }
+    }
+
+    #[test]
+    fn streams_deliver_what_batch_searches_find() {
+        let files = vec![
+            "resources/test1.txt".to_string(),
+            "resources/missing.txt".to_string(),
+            "resources/test1.txt".to_string(),
+        ];
+
+        for strategy in STRATEGIES {
+            let batch = search(strategy, "file", files.clone());
+
+            let in_order =
+                grep_stream(strategy, "file", files.clone(), Delivery::InOrder).collect::<Vec<_>>();
+            assert!(matches!(&in_order[3], Err(GrepError::NotFound(_))));
+            let in_order_matches = in_order.into_iter().filter_map(Result::ok);
+            assert_eq!(in_order_matches.collect::<Vec<_>>(), batch.matches);
+
+            let as_found = grep_stream(strategy, "file", files.clone(), Delivery::AsFound);
+            let (matches, errors): (Vec<_>, Vec<_>) = as_found.partition(Result::is_ok);
+            assert_eq!((matches.len(), errors.len()), (6, 1));
+        }
+    }
+
+    #[test]
+    fn options_are_honored_by_every_strategy() {
+        let files = vec!["resources/test1.txt".to_string()];
+        let query = Query::new(
+            Pattern::with_options(
+                &["FILE"],
+                &PatternOptions {
+                    case_insensitive: true,
+                    whole_word: true,
+                    fuzzy: None,
+                    multiline: false,
+                },
+            )
+            .unwrap(),
+            SearchOptions {
+                invert: true,
+                max_count: Some(2),
+                before_context: 1,
+                after_context: 1,
+                decompress: false,
+                text: false,
+                multiline: None,
+            },
+        );
+
+        for result in [
+            grep_seq(query.clone(), files.clone()),
+            grep_conc(query.clone(), files.clone()),
+            grep_chunk(query.clone(), files.clone()),
+            grep_pool(query.clone(), files.clone(), 2),
+            grep_ranges(query.clone(), files.clone()),
+        ] {
+            let summary = result
+                .matches
+                .iter()
+                .map(|found| (found.before.len(), found.line_number, found.after.len()))
+                .collect::<Vec<_>>();
+            assert_eq!(summary, vec![(1, 3, 1), (1, 5, 0)]);
+        }
+    }
 }
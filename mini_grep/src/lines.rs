@@ -0,0 +1,186 @@
+use crate::cancel::CancelToken;
+use crate::encoding::{decode_line, Text};
+use crate::error::GrepError;
+use crate::gzip::Input;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::mem;
+use std::sync::Arc;
+
+/// A line read from a file, along with where it starts inside that file.
+#[derive(Clone)]
+pub(crate) struct NumberedLine {
+    pub number: usize,
+    pub offset: u64,
+    pub text: String,
+}
+
+/// Like `BufRead::lines`, but keeps track of 1-based line numbers and byte offsets,
+/// and reads lines that are not UTF-8 as Latin-1 instead of failing.
+pub(crate) struct NumberedLines<R> {
+    reader: R,
+    path: Arc<str>,
+    number: usize,
+    offset: u64,
+    binary: bool,
+    /// Whether what is read was transcoded from UTF-16, so that offsets count the
+    /// two bytes of every code unit instead of what comes out.
+    utf16: bool,
+}
+
+/// Opens `path` for reading its lines, decompressing it as it is read if it is gzip
+/// and `decompress` is set. Offsets are then those of the decompressed text.
+pub(crate) fn open_numbered_lines(
+    path: &Arc<str>,
+    decompress: bool,
+) -> Result<NumberedLines<Text<Input<BufReader<File>>>>, GrepError> {
+    let file = File::open(&**path).map_err(|err| GrepError::from_io(path, 0, err))?;
+    numbered_lines(BufReader::new(file), path.clone(), decompress)
+        .map_err(|err| GrepError::from_io(path, 0, err))
+}
+
+/// Reads the lines of `reader`, decompressing it if it is gzip and `decompress` is set,
+/// and transcoding it to UTF-8 if it is UTF-16. Offsets are those of the decompressed
+/// text, before it is transcoded.
+pub(crate) fn numbered_lines<R: BufRead>(
+    reader: R,
+    path: Arc<str>,
+    decompress: bool,
+) -> io::Result<NumberedLines<Text<Input<R>>>> {
+    let (text, binary) = Text::sniff(Input::sniff(reader, decompress)?)?;
+    let utf16 = matches!(text, Text::Utf16(_));
+    let mut lines = NumberedLines::new(text, path);
+    lines.binary = binary;
+    if utf16 {
+        lines.utf16 = true;
+        // Past the byte order mark
+        lines.offset = 2;
+    }
+    Ok(lines)
+}
+
+impl<R: BufRead> NumberedLines<R> {
+    /// Reads lines from `reader` from its start, reporting errors as coming from `path`.
+    pub fn new(reader: R, path: Arc<str>) -> NumberedLines<R> {
+        NumberedLines {
+            reader,
+            path,
+            number: 0,
+            offset: 0,
+            binary: false,
+            utf16: false,
+        }
+    }
+
+    /// Whether the start of what is read holds a NUL byte, like binary files do.
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+}
+
+/// A piece of a file to be scanned on its own, with copies of the lines around it
+/// so that matches near its edges still get their whole context.
+pub(crate) struct Chunk {
+    pub leading: Vec<NumberedLine>,
+    pub lines: Vec<NumberedLine>,
+    pub trailing: Vec<NumberedLine>,
+    /// Whether the file the chunk belongs to looks binary.
+    pub binary: bool,
+}
+
+/// Reads `lines` in chunks of up to `chunk_size` lines, handing each one to `on_chunk`.
+///
+/// Each chunk carries the `context.0` lines before it and the `context.1` lines after
+/// it, so a chunk is only handed over once enough of the following ones were read.
+/// Reading stops early, as if the file was over, once `stop` is cancelled.
+pub(crate) fn for_each_chunk<R: BufRead>(
+    mut lines: NumberedLines<R>,
+    chunk_size: usize,
+    context: (usize, usize),
+    stop: &CancelToken,
+    mut on_chunk: impl FnMut(Chunk),
+) -> Result<(), GrepError> {
+    let (before, after) = context;
+    let mut leading: VecDeque<NumberedLine> = VecDeque::new();
+    let mut awaiting_trailing: VecDeque<Chunk> = VecDeque::new();
+
+    loop {
+        let chunk_lines = if stop.is_cancelled() {
+            vec![]
+        } else {
+            lines
+                .by_ref()
+                .take(chunk_size)
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        if chunk_lines.is_empty() {
+            // Chunks at the end of the file get whatever lines there were after them
+            awaiting_trailing.into_iter().for_each(&mut on_chunk);
+            return Ok(());
+        }
+
+        for chunk in awaiting_trailing.iter_mut() {
+            let missing = after - chunk.trailing.len();
+            chunk
+                .trailing
+                .extend(chunk_lines.iter().take(missing).cloned());
+        }
+
+        let next_leading = leading
+            .iter()
+            .chain(chunk_lines.iter())
+            .skip((leading.len() + chunk_lines.len()).saturating_sub(before))
+            .cloned()
+            .collect();
+        awaiting_trailing.push_back(Chunk {
+            leading: mem::replace(&mut leading, next_leading).into(),
+            lines: chunk_lines,
+            trailing: vec![],
+            binary: lines.is_binary(),
+        });
+
+        while awaiting_trailing
+            .front()
+            .is_some_and(|chunk| chunk.trailing.len() == after)
+        {
+            on_chunk(awaiting_trailing.pop_front().unwrap());
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for NumberedLines<R> {
+    type Item = Result<NumberedLine, GrepError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = vec![];
+        let read_bytes = match self.reader.read_until(b'\n', &mut bytes) {
+            Ok(0) => return None,
+            Ok(read_bytes) => read_bytes,
+            Err(err) => return Some(Err(GrepError::from_io(&self.path, self.number + 1, err))),
+        };
+        let read_bytes = match self.utf16 {
+            true => 2 * String::from_utf8_lossy(&bytes).encode_utf16().count(),
+            false => read_bytes,
+        };
+
+        if bytes.ends_with(b"\n") {
+            bytes.pop();
+            if bytes.ends_with(b"\r") {
+                bytes.pop();
+            }
+        }
+
+        let line = NumberedLine {
+            number: self.number + 1,
+            offset: self.offset,
+            text: decode_line(bytes, self.number == 0),
+        };
+        self.number += 1;
+        self.offset += read_bytes as u64;
+
+        Some(Ok(line))
+    }
+}
@@ -0,0 +1,111 @@
+/// Byte range of a single match inside its line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    /// Index of the expression that matched, for patterns built from several of them.
+    pub pattern: usize,
+    /// Edits between the matched text and the expression, for fuzzy patterns.
+    pub edits: Option<usize>,
+}
+
+/// A line that matched the pattern, and where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub path: String,
+    /// 1-based line number inside `path`.
+    pub line_number: usize,
+    /// Offset of the first byte of the line inside `path`.
+    pub byte_offset: u64,
+    /// The matching line, or every line of the match, joined by `\n`, for searches
+    /// whose matches may span lines. Lines that are not UTF-8 are transcoded, so they
+    /// may take more or fewer bytes here than in the file.
+    pub line: String,
+    /// Where the pattern matched inside `line`. Empty for inverted searches.
+    pub spans: Vec<Span>,
+    /// Lines right before this one, when the search asked for context.
+    pub before: Vec<ContextLine>,
+    /// Lines right after this one, when the search asked for context.
+    pub after: Vec<ContextLine>,
+    /// Whether the file looks binary. Like grep, only that it matched should be shown,
+    /// unless the search asked for binary files to be searched as text.
+    pub binary: bool,
+}
+
+/// A line shown around a match to give it context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextLine {
+    pub line_number: usize,
+    pub byte_offset: u64,
+    pub line: String,
+}
+
+impl Match {
+    pub(crate) fn new(
+        path: &str,
+        line_number: usize,
+        byte_offset: u64,
+        line: &str,
+        spans: Vec<Span>,
+    ) -> Match {
+        Match {
+            path: path.to_string(),
+            line_number,
+            byte_offset,
+            line: line.to_string(),
+            spans,
+            before: vec![],
+            after: vec![],
+            binary: false,
+        }
+    }
+
+    /// 1-based number of the last line of the match, past `line_number` only when a
+    /// multiline search matched across lines.
+    pub fn end_line_number(&self) -> usize {
+        self.line_number + self.line.matches('\n').count()
+    }
+
+    pub fn matched_texts(&self) -> impl Iterator<Item = &str> {
+        self.spans
+            .iter()
+            .map(|span| &self.line[span.start..span.end])
+    }
+
+    /// Fewest edits any match of the line needed, for fuzzy patterns.
+    pub fn edits(&self) -> Option<usize> {
+        self.spans.iter().filter_map(|span| span.edits).min()
+    }
+
+    /// Indexes of the expressions that matched this line, without repetitions.
+    pub fn matched_patterns(&self) -> Vec<usize> {
+        let mut patterns = self
+            .spans
+            .iter()
+            .map(|span| span.pattern)
+            .collect::<Vec<_>>();
+        patterns.sort_unstable();
+        patterns.dedup();
+        patterns
+    }
+
+    /// Moves the match and its context lines down by `lines`, for pieces of a file
+    /// whose line numbers were counted from the start of the piece.
+    pub(crate) fn shift_lines(&mut self, lines: usize) {
+        self.line_number += lines;
+        self.before
+            .iter_mut()
+            .chain(self.after.iter_mut())
+            .for_each(|line| line.line_number += lines);
+    }
+}
+
+impl ContextLine {
+    pub(crate) fn new(line_number: usize, byte_offset: u64, line: &str) -> ContextLine {
+        ContextLine {
+            line_number,
+            byte_offset,
+            line: line.to_string(),
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use crate::cancel::CancelToken;
+use crate::filter::LineFilter;
+use crate::lines::{Chunk, NumberedLine};
+use crate::matches::{ContextLine, Match, Span};
+use crate::pattern::Pattern;
+use crate::query::Query;
+use std::collections::BTreeMap;
+use std::iter;
+use std::ops::Range;
+use std::sync::Mutex;
+
+/// Most lines a match of a multiline search spans, unless the search asks for another limit.
+pub const DEFAULT_MULTILINE_LINES: usize = 64;
+
+/// Matches of the same line, along with every line up to where the last of them ends.
+struct Region {
+    first: usize,
+    last: usize,
+    /// Byte ranges inside the joined text.
+    spans: Vec<Span>,
+    /// Whether the region is reported by the chunk it was found in, rather than by
+    /// one of the chunks around it.
+    own: bool,
+}
+
+/// Lines joined into a single text, so that matches may cross from one to the next.
+struct Joined {
+    lines: Vec<NumberedLine>,
+    text: String,
+    starts: Vec<usize>,
+}
+
+impl Joined {
+    fn new(lines: Vec<NumberedLine>) -> Joined {
+        let mut text = String::new();
+        let mut starts = vec![];
+        for (index, line) in lines.iter().enumerate() {
+            if index > 0 {
+                text.push('\n');
+            }
+            starts.push(text.len());
+            text.push_str(&line.text);
+        }
+        Joined {
+            lines,
+            text,
+            starts,
+        }
+    }
+
+    fn end(&self, line: usize) -> usize {
+        self.starts[line] + self.lines[line].text.len()
+    }
+
+    /// The line a byte of the text is in. Newlines belong to the line they end.
+    fn line_at(&self, position: usize) -> usize {
+        self.starts.partition_point(|&start| start <= position) - 1
+    }
+
+    /// The line a match ends in. Empty matches are in the line they are at.
+    fn last_line(&self, span: &Span) -> usize {
+        self.line_at(span.end.saturating_sub(1).max(span.start))
+    }
+
+    fn lines_spanned(&self, span: &Span) -> usize {
+        self.last_line(span) - self.line_at(span.start) + 1
+    }
+
+    /// Where `line_number` starts, if it is one of the lines.
+    fn start_of(&self, line_number: usize) -> Option<usize> {
+        let line = line_number.checked_sub(self.lines.first()?.number)?;
+        self.starts.get(line).copied()
+    }
+
+    /// Matches from `from` on, one after the other like `Regex::find_iter` finds them.
+    /// `after_match` tells that a match ended right at `from`, so an empty one may not
+    /// start there. A match spanning more than `max_lines` lines is looked for again
+    /// in that many lines from where it starts, and left out if none starts there.
+    fn search<'a>(
+        &'a self,
+        pattern: &'a Pattern,
+        from: usize,
+        after_match: bool,
+        max_lines: usize,
+        stop: &'a CancelToken,
+    ) -> Box<dyn Iterator<Item = Span> + 'a> {
+        if let Pattern::Fuzzy(_) = pattern {
+            // Fuzzy matches are no longer than an expression and its edits, and finding
+            // them one at a time would search the rest of the text every time
+            let found = pattern.find_spans(&self.text[from..]).into_iter();
+            return Box::new(
+                found
+                    .map(move |span| Span {
+                        start: from + span.start,
+                        end: from + span.end,
+                        ..span
+                    })
+                    .filter(move |span| self.lines_spanned(span) <= max_lines),
+            );
+        }
+
+        let mut at = from;
+        let mut last_end = after_match.then_some(from);
+        Box::new(iter::from_fn(move || {
+            while at <= self.text.len() && !stop.is_cancelled() {
+                let mut span = pattern.find_at(&self.text, at)?;
+                if self.lines_spanned(&span) > max_lines {
+                    let last = self.line_at(span.start) + max_lines - 1;
+                    let limit = (self.end(last) + 1).min(self.text.len());
+                    match pattern.find_at(&self.text[..limit], span.start) {
+                        Some(shorter)
+                            if shorter.start == span.start
+                                && self.lines_spanned(&shorter) <= max_lines =>
+                        {
+                            span = shorter
+                        }
+                        _ => {
+                            at = self.next_char(span.start);
+                            continue;
+                        }
+                    }
+                }
+
+                let is_empty = span.start == span.end;
+                at = match is_empty {
+                    true => self.next_char(span.end),
+                    false => span.end,
+                };
+                if is_empty && last_end == Some(span.end) {
+                    continue;
+                }
+                last_end = Some(span.end);
+                return Some(span);
+            }
+            None
+        }))
+    }
+
+    fn next_char(&self, position: usize) -> usize {
+        position
+            + self.text[position..]
+                .chars()
+                .next()
+                .map_or(1, char::len_utf8)
+    }
+
+    /// The matches, grouped by the line they start in.
+    fn regions(&self, found: &[Span], own: &Range<usize>) -> Vec<Region> {
+        let mut regions: Vec<Region> = vec![];
+        for span in found {
+            let (first, last) = (self.line_at(span.start), self.last_line(span));
+            match regions.last_mut() {
+                Some(region) if region.first == first => {
+                    region.last = region.last.max(last);
+                    region.spans.push(*span);
+                }
+                _ => regions.push(Region {
+                    first,
+                    last,
+                    spans: vec![*span],
+                    own: own.contains(&first),
+                }),
+            }
+        }
+        regions
+    }
+
+    fn found(&self, path: &str, region: Region) -> Match {
+        let first = &self.lines[region.first];
+        let start = self.starts[region.first];
+        let spans = region
+            .spans
+            .into_iter()
+            .map(|span| Span {
+                start: span.start - start,
+                end: span.end - start,
+                ..span
+            })
+            .collect();
+        let text = &self.text[start..self.end(region.last)];
+        Match::new(path, first.number, first.offset, text, spans)
+    }
+}
+
+fn max_lines(query: &Query) -> usize {
+    query
+        .options
+        .multiline
+        .unwrap_or(DEFAULT_MULTILINE_LINES)
+        .max(1)
+}
+
+/// A chunk whose matches were looked for as if no match of the chunks before it
+/// reached into it, which `Seams` checks once those are over.
+pub(crate) struct ScannedChunk {
+    joined: Joined,
+    own: Range<usize>,
+    binary: bool,
+    /// Matches from the start of the chunk's own lines through its trailing lines.
+    found: Vec<Span>,
+}
+
+/// Searches the lines of a chunk once, as a single text. The lines after it, as many
+/// as `Query::overlap` asks for, let the matches starting near its end be found whole,
+/// along with the ones that cut their context short.
+pub(crate) fn scan_chunk(chunk: Chunk, query: &Query, stop: &CancelToken) -> ScannedChunk {
+    let own = chunk.leading.len()..chunk.leading.len() + chunk.lines.len();
+    let binary = chunk.binary;
+    let lines = chunk
+        .leading
+        .into_iter()
+        .chain(chunk.lines)
+        .chain(chunk.trailing)
+        .collect();
+    let joined = Joined::new(lines);
+    let from = joined.starts[own.start];
+    let found = joined
+        .search(&query.pattern, from, false, max_lines(query), stop)
+        .collect();
+    ScannedChunk {
+        joined,
+        own,
+        binary,
+        found,
+    }
+}
+
+/// Puts the chunks of a file back together, in order. A match may reach from a chunk
+/// into the next ones, and searching then goes on from where it ends rather than from
+/// where they start, so those are searched again from there, until a match is one
+/// they found on their own: from then on, they found the same.
+#[derive(Default)]
+pub(crate) struct Seams {
+    state: Mutex<SeamsState>,
+}
+
+#[derive(Default)]
+struct SeamsState {
+    waiting: BTreeMap<usize, ScannedChunk>,
+    next_piece: usize,
+    /// Line number where the last match so far ends, and the byte of that line.
+    resume: Option<(usize, usize)>,
+    /// First and last line numbers of the latest matches, which the next chunks may
+    /// have as context, or may even start inside.
+    recent: Vec<(usize, usize)>,
+}
+
+impl Seams {
+    /// Hands over chunk number `piece` of the file once it is scanned, and returns the
+    /// matches of every chunk that is now over, in order. That may be none, as long as
+    /// an earlier chunk is still being scanned.
+    pub fn piece_scanned(
+        &self,
+        piece: usize,
+        scanned: ScannedChunk,
+        file_name: &str,
+        query: &Query,
+        stop: &CancelToken,
+    ) -> Vec<(usize, Vec<Match>)> {
+        let state = &mut *self.state.lock().unwrap();
+        state.waiting.insert(piece, scanned);
+
+        let mut over = vec![];
+        while let Some(scanned) = state.waiting.remove(&state.next_piece) {
+            over.push((
+                state.next_piece,
+                state.stitch(scanned, file_name, query, stop),
+            ));
+            state.next_piece += 1;
+        }
+        over
+    }
+}
+
+impl SeamsState {
+    fn stitch(
+        &mut self,
+        scanned: ScannedChunk,
+        file_name: &str,
+        query: &Query,
+        stop: &CancelToken,
+    ) -> Vec<Match> {
+        let ScannedChunk {
+            joined,
+            own,
+            binary,
+            mut found,
+        } = scanned;
+
+        let resume = self
+            .resume
+            .and_then(|(line_number, byte)| Some(joined.start_of(line_number)? + byte));
+        if let Some(resume) = resume.filter(|&resume| resume >= joined.starts[own.start]) {
+            let mut again = vec![];
+            for span in joined.search(&query.pattern, resume, true, max_lines(query), stop) {
+                match found.binary_search_by_key(&span.start, |found| found.start) {
+                    Ok(same) if found[same].end == span.end => {
+                        again.extend_from_slice(&found[same..]);
+                        break;
+                    }
+                    _ => again.push(span),
+                }
+            }
+            found = again;
+        }
+
+        let own_regions = joined.regions(&found, &own);
+        let first_number = joined.lines[0].number;
+        let mut regions = self
+            .recent
+            .iter()
+            .filter(|(_, last)| *last >= first_number)
+            .map(|&(first, last)| Region {
+                first: first.saturating_sub(first_number),
+                last: (last - first_number).min(joined.lines.len() - 1),
+                spans: vec![],
+                own: false,
+            })
+            .collect::<Vec<_>>();
+        regions.extend(own_regions);
+
+        // Later chunks go on from the last match starting in this one's own lines
+        let own_end = joined.starts.get(own.end).copied().unwrap_or(usize::MAX);
+        if let Some(last) = found.iter().take_while(|span| span.start < own_end).last() {
+            let line = joined.line_at(last.end);
+            self.resume = Some((joined.lines[line].number, last.end - joined.starts[line]));
+        }
+        let next_number = joined.lines[own.end - 1].number + 1;
+        self.recent
+            .extend(regions.iter().filter(|region| region.own).map(|region| {
+                (
+                    joined.lines[region.first].number,
+                    joined.lines[region.last].number,
+                )
+            }));
+        self.recent
+            .retain(|&(_, last)| last + query.options.before_context >= next_number);
+
+        filter_regions(joined, own, binary, regions, file_name, query, stop)
+    }
+}
+
+/// Hands the lines of a chunk to the filter, each match as a whole. Matches of the
+/// chunks around it only cut the context of its own short.
+fn filter_regions(
+    joined: Joined,
+    own: Range<usize>,
+    binary: bool,
+    regions: Vec<Region>,
+    file_name: &str,
+    query: &Query,
+    stop: &CancelToken,
+) -> Vec<Match> {
+    let mut filter = LineFilter::new(file_name, query, binary);
+
+    for unit in units(regions, &own, joined.lines.len(), query.options.invert) {
+        let first = match &unit {
+            Unit::Line(line) => *line,
+            Unit::Selected(region) => region.first,
+        };
+        let is_own = own.contains(&first);
+        if is_own && stop.is_cancelled() || first >= own.end && !filter.wants_context() {
+            break;
+        }
+
+        let keep_going = match unit {
+            Unit::Line(line) => {
+                let line = &joined.lines[line];
+                let line = ContextLine::new(line.number, line.offset, &line.text);
+                match is_own {
+                    true => filter.push_unselected(line),
+                    false => {
+                        filter.add_context(line);
+                        true
+                    }
+                }
+            }
+            Unit::Selected(region) if region.own => {
+                filter.push_selected(joined.found(file_name, region))
+            }
+            Unit::Selected(_) => {
+                filter.skip_selected();
+                true
+            }
+        };
+        if !keep_going {
+            break;
+        }
+    }
+
+    filter.finish()
+}
+
+/// What is handed to the filter: a line that is not selected, or selected lines.
+enum Unit {
+    Line(usize),
+    Selected(Region),
+}
+
+/// Splits `lines` into what the filter is handed, in order. Inverted searches select
+/// every line that no match touches, one at a time.
+fn units(mut regions: Vec<Region>, own: &Range<usize>, lines: usize, invert: bool) -> Vec<Unit> {
+    if invert {
+        let mut touched = vec![false; lines];
+        for region in &regions {
+            touched[region.first..=region.last].fill(true);
+        }
+        return (0..lines)
+            .map(|line| match touched[line] {
+                true => Unit::Line(line),
+                false => Unit::Selected(Region {
+                    first: line,
+                    last: line,
+                    spans: vec![],
+                    own: own.contains(&line),
+                }),
+            })
+            .collect();
+    }
+
+    // A match may start in the line where the previous one ends, which it then shares
+    regions.sort_by_key(|region| region.first);
+    let mut units = vec![];
+    let mut next = 0;
+    for region in regions {
+        units.extend((next..region.first).map(Unit::Line));
+        next = next.max(region.last + 1);
+        units.push(Unit::Selected(region));
+    }
+    units.extend((next..lines).map(Unit::Line));
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::STRATEGIES;
+    use crate::{SearchConfig, DEFAULT_MULTILINE_LINES};
+
+    fn search(config: SearchConfig) -> Vec<(usize, usize, Vec<usize>, Vec<usize>)> {
+        let numbers = |lines: &[crate::ContextLine]| {
+            lines
+                .iter()
+                .map(|line| line.line_number)
+                .collect::<Vec<_>>()
+        };
+        config
+            .search(vec!["resources/trace.log".to_string()])
+            .unwrap()
+            .matches
+            .iter()
+            .map(|found| {
+                (
+                    found.line_number,
+                    found.end_line_number(),
+                    numbers(&found.before),
+                    numbers(&found.after),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_crossing_chunks_are_reported_once_by_every_strategy() {
+        let traces = || {
+            SearchConfig::any_of([r"\w+Exception: .*(\n\s+at .*)+", r"\{\n(.*\n)*?\}"])
+                .multiline(Some(DEFAULT_MULTILINE_LINES))
+        };
+        assert_eq!(
+            search(traces()),
+            [
+                (3, 6, vec![], vec![]),
+                (8, 11, vec![], vec![]),
+                (13, 14, vec![], vec![])
+            ]
+        );
+
+        for strategy in STRATEGIES {
+            for chunk_size in [1, 2, 3, 5] {
+                let found = search(traces().strategy(strategy).chunk_size(chunk_size));
+                assert_eq!(found, search(traces()), "{:?} {}", strategy, chunk_size);
+            }
+        }
+    }
+
+    #[test]
+    fn options_apply_to_whole_matches() {
+        let traces = || {
+            SearchConfig::new(r"^\s+at .*\n\S")
+                .multiline(Some(DEFAULT_MULTILINE_LINES))
+                .context(1)
+                .max_count(Some(2))
+        };
+        assert_eq!(
+            search(traces()),
+            [(6, 7, vec![5], vec![8]), (14, 15, vec![13], vec![])]
+        );
+        let inverted = || {
+            SearchConfig::new(r"\n\s+at")
+                .multiline(Some(DEFAULT_MULTILINE_LINES))
+                .invert(true)
+                .max_count(Some(4))
+        };
+        let lines = search(inverted()).into_iter().map(|found| found.0);
+        assert_eq!(lines.collect::<Vec<_>>(), [1, 2, 7, 8]);
+
+        for strategy in STRATEGIES {
+            for chunk_size in [1, 4] {
+                for config in [traces(), inverted()] {
+                    let found = search(config.clone().strategy(strategy).chunk_size(chunk_size));
+                    assert_eq!(found, search(config), "{:?} {}", strategy, chunk_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_longer_than_the_limit_are_left_out() {
+        // The payload takes 4 lines, and the lines of a trace are as many as fit
+        let blocks = |max_lines| {
+            SearchConfig::any_of([r"\{\n(.*\n)*?\}", r"\w+Exception: .*(\n\s+at .*)+"])
+                .multiline(Some(max_lines))
+        };
+        assert_eq!(
+            search(blocks(3)),
+            [(3, 5, vec![], vec![]), (13, 14, vec![], vec![])]
+        );
+        assert_eq!(
+            search(blocks(4)),
+            [
+                (3, 6, vec![], vec![]),
+                (8, 11, vec![], vec![]),
+                (13, 14, vec![], vec![])
+            ]
+        );
+
+        for strategy in STRATEGIES {
+            for chunk_size in [1, 2, 5] {
+                for max_lines in [3, 4] {
+                    let found = search(blocks(max_lines).strategy(strategy).chunk_size(chunk_size));
+                    assert_eq!(
+                        found,
+                        search(blocks(max_lines)),
+                        "{:?} {}",
+                        strategy,
+                        chunk_size
+                    );
+                }
+            }
+        }
+    }
+}
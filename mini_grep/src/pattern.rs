@@ -0,0 +1,521 @@
+use crate::fuzzy::Fuzzy;
+use crate::matches::Span;
+use crate::replace::{GroupRef, Replacement};
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use regex::{Captures, Regex, RegexBuilder};
+use regex_syntax::ast::parse::Parser;
+use regex_syntax::ast::{Ast, GroupKind};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// A search pattern compiled once and shared read-only between worker threads.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Literal(String),
+    /// Several literals searched at once by a single Aho-Corasick automaton.
+    Literals {
+        automaton: AhoCorasick,
+        literals: Vec<String>,
+    },
+    Regex(Regex),
+    /// Several expressions joined into one regex. Each of them is wrapped in a
+    /// capture group, whose index is kept to tell which one matched, along with the
+    /// index of every named group inside it.
+    Alternatives {
+        regex: Regex,
+        groups: Vec<usize>,
+        names: Vec<HashMap<String, usize>>,
+    },
+    /// Plain text matched within a few edits, reported by every span.
+    Fuzzy(Fuzzy),
+}
+
+/// How the expressions given to `Pattern::with_options` are interpreted.
+#[derive(Debug, Clone, Default)]
+pub struct PatternOptions {
+    pub case_insensitive: bool,
+    /// Only match whole words, like `grep -w`.
+    pub whole_word: bool,
+    /// Match the expressions as plain text within this many edits, like `agrep -k`.
+    pub fuzzy: Option<usize>,
+    /// Let `^` and `$` match at the start and end of every line of the text, for
+    /// searches whose matches span lines.
+    pub multiline: bool,
+}
+
+impl Pattern {
+    pub fn literal(text: impl Into<String>) -> Pattern {
+        Pattern::Literal(text.into())
+    }
+
+    /// Compiles a regular expression. Expressions without any metacharacter
+    /// keep the literal fast path instead of going through the regex engine.
+    pub fn regex(expression: &str) -> Result<Pattern, regex::Error> {
+        if regex::escape(expression) == expression {
+            return Ok(Pattern::literal(expression));
+        }
+        Ok(Pattern::Regex(Regex::new(expression)?))
+    }
+
+    /// Compiles one or more expressions into a single pattern matching any of them.
+    /// Spans tell which one matched by its index in `expressions`.
+    pub fn with_options(
+        expressions: &[impl AsRef<str>],
+        options: &PatternOptions,
+    ) -> Result<Pattern, regex::Error> {
+        if let Some(max_edits) = options.fuzzy {
+            return Ok(Pattern::fuzzy(expressions, max_edits, options));
+        }
+        if expressions.is_empty() {
+            // An empty class, which no character is in, so not even whole words match
+            return Ok(Pattern::Regex(Regex::new(r"[^\s\S]")?));
+        }
+        if let [expression] = expressions {
+            if !options.case_insensitive && !options.whole_word && !options.multiline {
+                return Pattern::regex(expression.as_ref());
+            }
+        }
+        if let Some(literals) = Pattern::literals(expressions, options) {
+            return Ok(literals);
+        }
+
+        let build = |expression: &str| {
+            RegexBuilder::new(expression)
+                .case_insensitive(options.case_insensitive)
+                .multi_line(options.multiline)
+                .build()
+        };
+        let word = |expression: String| match options.whole_word {
+            true => format!(r"\b(?:{})\b", expression),
+            false => expression,
+        };
+
+        if let [expression] = expressions {
+            return Ok(Pattern::Regex(build(&word(
+                expression.as_ref().to_string(),
+            ))?));
+        }
+
+        // Group 0 is the whole match, and every expression may have groups of its own.
+        // Their names are taken out, since expressions may share them, and kept apart
+        let mut groups = vec![];
+        let mut names = vec![];
+        let mut alternatives = vec![];
+        let mut next_group = 1;
+        for expression in expressions {
+            let expression = expression.as_ref();
+            let regex = build(expression)?;
+            groups.push(next_group);
+            next_group += regex.captures_len();
+            names.push(
+                regex
+                    .capture_names()
+                    .enumerate()
+                    .filter_map(|(index, name)| Some((name?.to_string(), index)))
+                    .collect(),
+            );
+            alternatives.push(format!("({})", unname_groups(expression)));
+        }
+
+        Ok(Pattern::Alternatives {
+            regex: build(&word(alternatives.join("|")))?,
+            groups,
+            names,
+        })
+    }
+
+    /// Matches lines holding any of `expressions`, taken as plain text, with at most
+    /// `max_edits` characters inserted, deleted or replaced.
+    pub fn fuzzy(
+        expressions: &[impl AsRef<str>],
+        max_edits: usize,
+        options: &PatternOptions,
+    ) -> Pattern {
+        Pattern::Fuzzy(Fuzzy::new(
+            expressions,
+            max_edits,
+            options.case_insensitive,
+            options.whole_word,
+        ))
+    }
+
+    /// Several plain literals go through Aho-Corasick instead of a regex, as long as
+    /// the automaton can honor the options: it only ignores ASCII case.
+    fn literals(expressions: &[impl AsRef<str>], options: &PatternOptions) -> Option<Pattern> {
+        let all_literal = expressions.iter().all(|expression| {
+            let expression = expression.as_ref();
+            regex::escape(expression) == expression
+                && (!options.case_insensitive || expression.is_ascii())
+        });
+        if !all_literal || options.whole_word {
+            return None;
+        }
+
+        // Leftmost-first is what regex alternations do, so both find the same spans
+        AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .ascii_case_insensitive(options.case_insensitive)
+            .build(expressions.iter().map(|expression| expression.as_ref()))
+            .ok()
+            .map(|automaton| Pattern::Literals {
+                automaton,
+                literals: expressions
+                    .iter()
+                    .map(|expression| expression.as_ref().to_string())
+                    .collect(),
+            })
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match self {
+            Pattern::Literal(text) => line.contains(text.as_str()),
+            Pattern::Literals { automaton, .. } => automaton.is_match(line),
+            Pattern::Regex(regex) | Pattern::Alternatives { regex, .. } => regex.is_match(line),
+            Pattern::Fuzzy(fuzzy) => fuzzy.is_match(line),
+        }
+    }
+
+    /// Returns the byte range of every non-overlapping match inside `line`.
+    pub fn find_spans(&self, line: &str) -> Vec<Span> {
+        match self {
+            Pattern::Literal(text) => line
+                .match_indices(text.as_str())
+                .map(|(start, found)| Span {
+                    start,
+                    end: start + found.len(),
+                    pattern: 0,
+                    edits: None,
+                })
+                .collect(),
+            Pattern::Literals { automaton, .. } => automaton
+                .find_iter(line)
+                .map(|found| Span {
+                    start: found.start(),
+                    end: found.end(),
+                    pattern: found.pattern().as_usize(),
+                    edits: None,
+                })
+                .collect(),
+            Pattern::Regex(regex) => regex
+                .find_iter(line)
+                .map(|found| Span {
+                    start: found.start(),
+                    end: found.end(),
+                    pattern: 0,
+                    edits: None,
+                })
+                .collect(),
+            Pattern::Alternatives { regex, groups, .. } => regex
+                .captures_iter(line)
+                .map(|captures| alternative_span(&captures, groups))
+                .collect(),
+            Pattern::Fuzzy(fuzzy) => fuzzy.find_spans(line),
+        }
+    }
+
+    /// The leftmost match inside `text` that starts at `start` or after it. Unlike
+    /// searching what follows `start`, anchors and word boundaries see what precedes it.
+    /// Fuzzy patterns only search what follows it, every time.
+    pub(crate) fn find_at(&self, text: &str, start: usize) -> Option<Span> {
+        let span = |start, end| Span {
+            start,
+            end,
+            pattern: 0,
+            edits: None,
+        };
+        match self {
+            Pattern::Literal(literal) => text[start..]
+                .find(literal.as_str())
+                .map(|offset| span(start + offset, start + offset + literal.len())),
+            Pattern::Literals { automaton, .. } => automaton
+                .find(Input::new(text).span(start..text.len()))
+                .map(|found| Span {
+                    pattern: found.pattern().as_usize(),
+                    ..span(found.start(), found.end())
+                }),
+            Pattern::Regex(regex) => regex
+                .find_at(text, start)
+                .map(|found| span(found.start(), found.end())),
+            Pattern::Alternatives { regex, groups, .. } => regex
+                .captures_at(text, start)
+                .map(|captures| alternative_span(&captures, groups)),
+            Pattern::Fuzzy(fuzzy) => fuzzy.find_spans(&text[start..]).first().map(|found| Span {
+                start: start + found.start,
+                end: start + found.end,
+                ..*found
+            }),
+        }
+    }
+
+    /// Replaces every match inside `line`. Groups are numbered within the expression
+    /// that matched, as if it had been given on its own, and literals only have `$0`.
+    pub fn replace_all<'a>(&self, line: &'a str, replacement: &Replacement) -> Cow<'a, str> {
+        let mut replaced = String::new();
+        let mut copied = 0;
+        let mut replace = |start: usize, end: usize, expand: &dyn Fn(&mut String)| {
+            replaced.push_str(&line[copied..start]);
+            expand(&mut replaced);
+            copied = end;
+        };
+
+        match self {
+            Pattern::Literal(_) | Pattern::Literals { .. } | Pattern::Fuzzy(_) => {
+                for span in self.find_spans(line) {
+                    let found = &line[span.start..span.end];
+                    replace(span.start, span.end, &|out| {
+                        replacement.expand(out, |group| match group {
+                            GroupRef::Index(0) => Some(found),
+                            _ => None,
+                        })
+                    });
+                }
+            }
+            Pattern::Regex(regex) => {
+                for captures in regex.captures_iter(line) {
+                    let found = captures.get(0).unwrap();
+                    replace(found.start(), found.end(), &|out| {
+                        replacement.expand(out, |group| captured(&captures, group, 0, usize::MAX))
+                    });
+                }
+            }
+            Pattern::Alternatives {
+                regex,
+                groups,
+                names,
+            } => {
+                for captures in regex.captures_iter(line) {
+                    let found = captures.get(0).unwrap();
+                    let expression = groups
+                        .iter()
+                        .position(|&group| captures.get(group).is_some())
+                        .unwrap_or(0);
+                    // The groups of an expression come right after the one wrapping it
+                    let first = groups[expression];
+                    let last = groups
+                        .get(expression + 1)
+                        .map_or(regex.captures_len() - 1, |next| next - 1);
+                    replace(found.start(), found.end(), &|out| {
+                        replacement.expand(out, |group| match group {
+                            GroupRef::Name(name) => names[expression]
+                                .get(name)
+                                .and_then(|index| captures.get(first + index))
+                                .map(|found| found.as_str()),
+                            _ => captured(&captures, group, first, last),
+                        })
+                    });
+                }
+            }
+        }
+
+        if copied == 0 && replaced.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        replaced.push_str(&line[copied..]);
+        Cow::Owned(replaced)
+    }
+}
+
+/// Where the whole match is, and which expression of the alternatives matched.
+fn alternative_span(captures: &Captures, groups: &[usize]) -> Span {
+    let found = captures.get(0).unwrap();
+    Span {
+        start: found.start(),
+        end: found.end(),
+        pattern: groups
+            .iter()
+            .position(|&group| captures.get(group).is_some())
+            .unwrap_or(0),
+        edits: None,
+    }
+}
+
+/// What a group captured, for an expression whose own groups are `first + 1..=last`.
+fn captured<'a>(
+    captures: &Captures<'a>,
+    group: &GroupRef,
+    first: usize,
+    last: usize,
+) -> Option<&'a str> {
+    let found = match group {
+        GroupRef::Index(0) => captures.get(0),
+        GroupRef::Index(index) if first + index <= last => captures.get(first + index),
+        GroupRef::Index(_) => None,
+        GroupRef::Name(name) => captures.name(name),
+    };
+    found.map(|found| found.as_str())
+}
+
+/// Turns the named groups of a valid expression into plain ones, which keeps their indexes.
+fn unname_groups(expression: &str) -> String {
+    fn named(ast: &Ast, found: &mut Vec<(usize, usize)>) {
+        match ast {
+            Ast::Group(group) => {
+                if let GroupKind::CaptureName { name, .. } = &group.kind {
+                    // From right after the opening parenthesis through the closing `>`
+                    found.push((group.span.start.offset + 1, name.span.end.offset + 1));
+                }
+                named(&group.ast, found);
+            }
+            Ast::Repetition(repetition) => named(&repetition.ast, found),
+            Ast::Alternation(alternation) => {
+                alternation.asts.iter().for_each(|ast| named(ast, found))
+            }
+            Ast::Concat(concat) => concat.asts.iter().for_each(|ast| named(ast, found)),
+            _ => {}
+        }
+    }
+
+    let mut found = vec![];
+    if let Ok(ast) = Parser::new().parse(expression) {
+        named(&ast, &mut found);
+    }
+    let mut unnamed = expression.to_string();
+    for (start, end) in found.into_iter().rev() {
+        unnamed.replace_range(start..end, "");
+    }
+    unnamed
+}
+
+impl From<String> for Pattern {
+    fn from(text: String) -> Self {
+        Pattern::Literal(text)
+    }
+}
+
+impl From<&str> for Pattern {
+    fn from(text: &str) -> Self {
+        Pattern::literal(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_expression_uses_literal_fast_path() {
+        assert!(matches!(
+            Pattern::regex("thread").unwrap(),
+            Pattern::Literal(_)
+        ));
+    }
+
+    #[test]
+    fn regex_supports_classes_anchors_and_alternation() {
+        let pattern = Pattern::regex(r"^(This|We) (is|are) [a-z]+").unwrap();
+
+        assert!(pattern.is_match("This is a test file"));
+        assert!(pattern.is_match("We are multithreading!"));
+        assert!(!pattern.is_match("It's a file that's for testing"));
+    }
+
+    #[test]
+    fn spans_cover_every_match() {
+        let spans = Pattern::regex("a+").unwrap().find_spans("a baa caaa");
+
+        assert_eq!(
+            spans,
+            vec![
+                Span {
+                    start: 0,
+                    end: 1,
+                    pattern: 0,
+                    edits: None
+                },
+                Span {
+                    start: 3,
+                    end: 5,
+                    pattern: 0,
+                    edits: None
+                },
+                Span {
+                    start: 7,
+                    end: 10,
+                    pattern: 0,
+                    edits: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn options_combine_every_expression() {
+        let options = PatternOptions {
+            case_insensitive: true,
+            whole_word: true,
+            fuzzy: None,
+            multiline: false,
+        };
+        let pattern = Pattern::with_options(&["file", "THANKS"], &options).unwrap();
+
+        assert!(pattern.is_match("This is a test FILE"));
+        assert!(pattern.is_match("thanks!"));
+        assert!(!pattern.is_match("Files and thanksgiving"));
+    }
+
+    #[test]
+    fn several_literals_use_aho_corasick_and_tell_which_one_matched() {
+        let pattern =
+            Pattern::with_options(&["test", "file", "Thanks"], &PatternOptions::default()).unwrap();
+        assert!(matches!(pattern, Pattern::Literals { .. }));
+
+        let patterns = |line| {
+            pattern
+                .find_spans(line)
+                .iter()
+                .map(|span| span.pattern)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(patterns("Thanks for helping, file!"), vec![2, 1]);
+        assert_eq!(patterns("This is a test file"), vec![0, 1]);
+    }
+
+    #[test]
+    fn alternatives_tell_which_expression_matched() {
+        let options = PatternOptions {
+            case_insensitive: true,
+            ..PatternOptions::default()
+        };
+        let pattern =
+            Pattern::with_options(&[r"(t)e(s)t", r"help(ful|ing)", "FILE"], &options).unwrap();
+
+        let spans = pattern.find_spans("Thanks for helping, file! Test");
+        let found = spans
+            .iter()
+            .map(|span| (span.start, span.pattern))
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![(11, 1), (20, 2), (26, 0)]);
+    }
+
+    #[test]
+    fn no_expressions_match_nothing() {
+        let pattern = Pattern::with_options(&[] as &[&str], &PatternOptions::default()).unwrap();
+        assert!(!pattern.is_match("This is a test file"));
+
+        let options = PatternOptions {
+            whole_word: true,
+            ..PatternOptions::default()
+        };
+        let pattern = Pattern::with_options(&[] as &[&str], &options).unwrap();
+        assert!(!pattern.is_match("This is a test file"));
+        assert!(!pattern.is_match(""));
+    }
+
+    #[test]
+    fn expressions_may_share_group_names() {
+        let pattern = Pattern::with_options(
+            &[r"(?P<count>\d+) apples", r"(?<fruit>\w+) (?P<count>\d+)"],
+            &PatternOptions::default(),
+        )
+        .unwrap();
+
+        let replaced =
+            pattern.replace_all("3 apples, pears 4", &Replacement::new("<${count}${fruit}>"));
+        assert_eq!(replaced, "<3>, <4pears>");
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(Pattern::regex("(unclosed").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+pub(crate) type Task = Box<dyn Send + FnOnce()>;
+type SyncReceiverArc = Arc<Mutex<Receiver<Task>>>;
+
+/// Fixed amount of threads that take tasks from a shared queue as soon as they are free.
+pub(crate) struct WorkerPool {
+    spawner: Spawner,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// Handle used to queue tasks, which tasks themselves may hold to queue more work.
+#[derive(Clone)]
+pub(crate) struct Spawner(Sender<Task>);
+
+impl WorkerPool {
+    pub fn new(thread_amount: usize) -> WorkerPool {
+        let (tx, rx) = channel::<Task>();
+        let rx_arc = Arc::new(Mutex::new(rx));
+
+        let workers = (0..thread_amount.max(1))
+            .map(|_| {
+                let arc_clone = rx_arc.clone();
+                thread::spawn(|| check_and_run_tasks(arc_clone))
+            })
+            .collect();
+
+        WorkerPool {
+            spawner: Spawner(tx),
+            workers,
+        }
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        self.spawner.clone()
+    }
+
+    pub fn spawn(&self, task: impl Send + FnOnce() + 'static) {
+        self.spawner.spawn(task);
+    }
+
+    /// Waits for every queued task, including the ones queued by other tasks.
+    /// Workers stop once no `Spawner` is left, i.e. when no task can queue more work.
+    pub fn join(self) {
+        drop(self.spawner);
+        self.workers
+            .into_iter()
+            .for_each(|worker| worker.join().unwrap());
+    }
+}
+
+/// Anything tasks can be queued on, so chunks are queued the same way on every pool.
+pub(crate) trait Spawn: Send + 'static {
+    fn spawn(&self, task: impl Send + FnOnce() + 'static);
+}
+
+impl Spawn for Spawner {
+    fn spawn(&self, task: impl Send + FnOnce() + 'static) {
+        // Workers only stop after every spawner is dropped, so the queue is still open
+        self.0.send(Box::new(task)).unwrap();
+    }
+}
+
+fn check_and_run_tasks(sync_receiver_arc: SyncReceiverArc) {
+    loop {
+        let task_result = sync_receiver_arc
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .recv();
+        match task_result {
+            // A panicking task must not take its worker down with it: whoever
+            // waits for that task's result notices it is missing instead.
+            Ok(task) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(task));
+            }
+            Err(_) => {
+                return;
+            }
+        }
+    }
+}
+
+pub fn default_thread_amount() -> usize {
+    thread::available_parallelism().map_or(1, |amount| amount.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn join_waits_for_tasks_spawned_by_tasks() {
+        let pool = WorkerPool::new(2);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..10 {
+            let (spawner, counter) = (pool.spawner(), counter.clone());
+            pool.spawn(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                spawner.spawn(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            });
+        }
+        pool.join();
+
+        assert_eq!(counter.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn panicking_task_does_not_kill_its_worker() {
+        let pool = WorkerPool::new(1);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        pool.spawn(|| panic!("task failed"));
+        let counter_clone = counter.clone();
+        pool.spawn(move || {
+            counter_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        pool.join();
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::pattern::Pattern;
+
+/// Options that change which lines of a file are reported, honored by every strategy.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Report the lines that do not match instead, like `grep -v`.
+    pub invert: bool,
+    /// Stop after this many reported lines per file, like `grep -m`.
+    pub max_count: Option<usize>,
+    /// Lines to report before each match, like `grep -B`.
+    pub before_context: usize,
+    /// Lines to report after each match, like `grep -A`.
+    pub after_context: usize,
+    /// Search gzip-compressed files as the text they hold, like `zgrep`. Files are
+    /// told apart by their first bytes, so plain ones are still searched as they are.
+    pub decompress: bool,
+    /// Search binary files as text, like `grep -a`, so their matches are not told apart.
+    pub text: bool,
+    /// Let matches span up to this many lines, like `rg --multiline`. A match is
+    /// reported once, its `line` holding every line it spans. One that would span more
+    /// lines is looked for again within that many, and left out if it is not there.
+    /// Followed files and replacements are still matched line by line.
+    pub multiline: Option<usize>,
+}
+
+/// What to look for: the compiled pattern plus the search options,
+/// shared read-only by every worker of a search.
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub pattern: Pattern,
+    pub options: SearchOptions,
+}
+
+impl Query {
+    pub fn new(pattern: impl Into<Pattern>, options: SearchOptions) -> Query {
+        Query {
+            pattern: pattern.into(),
+            options,
+        }
+    }
+
+    /// Lines wanted before and after each match.
+    pub(crate) fn context(&self) -> (usize, usize) {
+        (self.options.before_context, self.options.after_context)
+    }
+
+    /// Lines a chunk of a file needs from before and after it: the context of its
+    /// matches and, for multiline searches, enough lines to find whole the matches
+    /// starting in it or in its after context.
+    pub(crate) fn overlap(&self) -> (usize, usize) {
+        let (before, after) = self.context();
+        match self.options.multiline {
+            Some(max_lines) => (before, after + 2 * max_lines),
+            None => (before, after),
+        }
+    }
+}
+
+impl From<Pattern> for Query {
+    fn from(pattern: Pattern) -> Self {
+        Query::new(pattern, SearchOptions::default())
+    }
+}
+
+impl From<String> for Query {
+    fn from(text: String) -> Self {
+        Query::from(Pattern::from(text))
+    }
+}
+
+impl From<&str> for Query {
+    fn from(text: &str) -> Self {
+        Query::from(Pattern::from(text))
+    }
+}
@@ -0,0 +1,411 @@
+use crate::cancel::CancelToken;
+use crate::encoding::{decode_bytes, is_utf16_file, looks_binary, BINARY_SNIFF_SIZE};
+use crate::error::GrepError;
+use crate::filter::LineFilter;
+use crate::gzip::is_gzip;
+use crate::matches::Match;
+use crate::query::Query;
+use crate::results::SearchResults;
+use crate::{default_thread_amount, scan_file_chunks, search, Job, Strategy};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+const RANGE_SIZE: u64 = 1 << 20;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// What a worker found inside its byte range. Line numbers are relative to the
+/// range, since the amount of lines in previous ranges is unknown until they finish.
+/// The `leading` context lines read from the previous range come first.
+struct RangeScan {
+    leading: usize,
+    lines: usize,
+    matches: Vec<Match>,
+}
+
+/// Splits every file into byte ranges that workers, one per CPU, read and scan on
+/// their own, so reading a big file is as parallel as matching it.
+pub fn grep_ranges(query: impl Into<Query>, file_names: Vec<String>) -> SearchResults {
+    search(Strategy::Ranges, query, file_names)
+}
+
+pub(crate) fn scan_file_ranges(file: usize, file_name: Arc<str>, job: Job) {
+    // Compressed and UTF-16 files cannot be split at any newline byte found from an
+    // offset, so they are read in chunks instead, and so are multiline searches,
+    // whose chunks need many more lines from their neighbours than context does
+    if job.query.options.decompress && is_gzip(&*file_name)
+        || is_utf16_file(&*file_name)
+        || job.query.options.multiline.is_some()
+    {
+        return scan_file_chunks(file, file_name, job);
+    }
+
+    let mut pieces = 0;
+    let thread_amount = job.thread_amount().unwrap_or_else(default_thread_amount);
+    let result = search_file_ranges(
+        &file_name,
+        RANGE_SIZE,
+        thread_amount,
+        &job.query,
+        &job.cancel,
+        |matches| {
+            job.report_piece(file, pieces, matches);
+            pieces += 1;
+        },
+    );
+
+    job.report_done(file, &file_name, pieces, result);
+}
+
+/// Hands the matches of every range to `on_range`, in order, as soon as the
+/// ranges before it are over, read by up to `thread_amount` workers. Ranges past
+/// the per-file limit are stopped.
+fn search_file_ranges(
+    file_name: &str,
+    range_size: u64,
+    thread_amount: usize,
+    query: &Query,
+    cancel: &CancelToken,
+    mut on_range: impl FnMut(Vec<Match>),
+) -> Result<(), GrepError> {
+    let file = File::open(file_name).map_err(|err| GrepError::from_io(file_name, 0, err))?;
+    let file_len = file
+        .metadata()
+        .map_err(|err| GrepError::from_io(file_name, 0, err))?
+        .len();
+    let mut head = vec![];
+    PositionedReader {
+        file: &file,
+        offset: 0,
+    }
+    .take(BINARY_SNIFF_SIZE as u64)
+    .read_to_end(&mut head)
+    .map_err(|err| GrepError::from_io(file_name, 0, err))?;
+    let binary = looks_binary(&head);
+
+    let range_size = range_size.max(1);
+    let range_count = file_len.div_ceil(range_size) as usize;
+    let next_range = AtomicUsize::new(0);
+    let stop = cancel.child();
+
+    thread::scope(|scope| {
+        let (tx, rx) = channel();
+        let workers = (0..thread_amount.min(range_count))
+            .map(|_| {
+                let (tx, file, next_range, stop) = (tx.clone(), &file, &next_range, &stop);
+                // Ranges are taken in order, so the ones stitched first are over first
+                scope.spawn(move || {
+                    while !stop.is_cancelled() {
+                        let range = next_range.fetch_add(1, Ordering::Relaxed);
+                        if range >= range_count {
+                            break;
+                        }
+                        let start = range as u64 * range_size;
+                        let end = (start + range_size).min(file_len);
+                        let scan = scan_range(file, file_name, start, end, query, binary, stop);
+                        if tx.send((range, scan)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        let result = stitch_ranges(rx, range_count, file_name, query, cancel, &mut on_range);
+        stop.cancel();
+        // A worker that panicked left its range out, which stitching reported already
+        for worker in workers {
+            let _ = worker.join();
+        }
+        result
+    })
+}
+
+/// Takes the scans of the ranges as they come, and hands them to `on_range` in order,
+/// turning range-relative line numbers into global ones.
+fn stitch_ranges(
+    scans: Receiver<(usize, Result<RangeScan, GrepError>)>,
+    range_count: usize,
+    file_name: &str,
+    query: &Query,
+    cancel: &CancelToken,
+    on_range: &mut impl FnMut(Vec<Match>),
+) -> Result<(), GrepError> {
+    let max_count = query.options.max_count.unwrap_or(usize::MAX);
+    let mut waiting = HashMap::new();
+    let mut lines_before = 0;
+    let mut found_amount = 0;
+
+    for range in 0..range_count {
+        // Past the per-file limit, later ranges (and their errors) do not matter
+        if found_amount >= max_count {
+            break;
+        }
+
+        while !waiting.contains_key(&range) {
+            match scans.recv() {
+                Ok((other, scan)) => {
+                    waiting.insert(other, scan);
+                }
+                // Every worker is gone without scanning it
+                Err(_) if cancel.is_cancelled() => {
+                    return Err(GrepError::Cancelled(file_name.to_string()));
+                }
+                Err(_) => return Err(GrepError::WorkerPanic(file_name.to_string())),
+            }
+        }
+
+        let scan = waiting
+            .remove(&range)
+            .unwrap()
+            .map_err(|err| match err {
+                GrepError::InvalidUtf8 { path, line_number } => GrepError::InvalidUtf8 {
+                    path,
+                    line_number: line_number + lines_before,
+                },
+                err => err,
+            })
+            // Cancelled ranges were cut short, so the lines they counted are wrong
+            .and_then(|scan| {
+                if cancel.is_cancelled() {
+                    Err(GrepError::Cancelled(file_name.to_string()))
+                } else {
+                    Ok(scan)
+                }
+            })?;
+
+        let matches = scan
+            .matches
+            .into_iter()
+            .take(max_count - found_amount)
+            .map(|mut found| {
+                found.shift_lines(lines_before - scan.leading);
+                found
+            })
+            .collect::<Vec<_>>();
+        found_amount += matches.len();
+        lines_before += scan.lines;
+        on_range(matches);
+    }
+
+    Ok(())
+}
+
+/// Scans the lines that start inside `[start, end)`. The last one may end past `end`,
+/// and the one `start` falls in the middle of belongs to the previous range.
+/// Lines of the neighbouring ranges are read as well when matches need them as context.
+fn scan_range(
+    file: &File,
+    file_name: &str,
+    start: u64,
+    end: u64,
+    query: &Query,
+    binary: bool,
+    stop: &CancelToken,
+) -> Result<RangeScan, GrepError> {
+    let to_grep_error = |line_number, err| GrepError::from_io(file_name, line_number, err);
+    let mut position = start.saturating_sub(1);
+    let mut reader = BufReader::with_capacity(
+        READ_BUFFER_SIZE,
+        PositionedReader {
+            file,
+            offset: position,
+        },
+    );
+    let mut line: Vec<u8> = vec![];
+
+    if start > 0 {
+        // Reading from the byte before `start` up to a newline skips exactly the
+        // tail of the previous range's last line, or nothing if it ended right there.
+        position += reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| to_grep_error(0, err))? as u64;
+    }
+
+    let mut filter = LineFilter::new(file_name, query, binary);
+    let leading = read_leading_lines(file, position, query.options.before_context)
+        .map_err(|err| to_grep_error(0, err))?;
+    for (number, (offset, text)) in leading.iter().enumerate() {
+        filter.push_context(number + 1, *offset, text);
+    }
+    let mut lines = 0;
+
+    while position < end && !stop.is_cancelled() {
+        line.clear();
+        let read_bytes = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| to_grep_error(lines + 1, err))?;
+        if read_bytes == 0 {
+            break;
+        }
+        lines += 1;
+
+        let line_number = leading.len() + lines;
+        let text = decode_bytes(trim_newline(&line), position == 0);
+        if !filter.push(line_number, position, &text) {
+            break;
+        }
+        position += read_bytes as u64;
+    }
+
+    // The last match may want lines from the next range as context
+    let mut trailing = 0;
+    while filter.wants_context() {
+        line.clear();
+        let read_bytes = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| to_grep_error(lines + trailing + 1, err))?;
+        if read_bytes == 0 {
+            break;
+        }
+        trailing += 1;
+
+        let text = decode_bytes(trim_newline(&line), false);
+        filter.push_context(leading.len() + lines + trailing, position, &text);
+        position += read_bytes as u64;
+    }
+
+    Ok(RangeScan {
+        leading: leading.len(),
+        lines,
+        matches: filter.finish(),
+    })
+}
+
+/// Reads backwards from `line_start` the up to `amount` lines before it, with their offsets.
+fn read_leading_lines(
+    file: &File,
+    line_start: u64,
+    amount: usize,
+) -> io::Result<Vec<(u64, String)>> {
+    if amount == 0 || line_start == 0 {
+        return Ok(vec![]);
+    }
+
+    // The byte before `line_start` is the newline ending the previous line, so
+    // `amount` complete lines are there once `amount + 1` newlines were read.
+    let mut start = line_start;
+    let mut bytes: Vec<u8> = vec![];
+    while start > 0 && bytes.iter().filter(|&&byte| byte == b'\n').count() <= amount {
+        let block_start = start.saturating_sub(READ_BUFFER_SIZE as u64);
+        let mut block = vec![0; (start - block_start) as usize];
+        PositionedReader {
+            file,
+            offset: block_start,
+        }
+        .read_exact(&mut block)?;
+        block.append(&mut bytes);
+        bytes = block;
+        start = block_start;
+    }
+
+    let mut offset = start;
+    let mut leading = vec![];
+    for line in bytes[..bytes.len() - 1].split(|&byte| byte == b'\n') {
+        let text = decode_bytes(trim_newline(line), offset == 0).into_owned();
+        leading.push((offset, text));
+        offset += line.len() as u64 + 1;
+    }
+    // Unless the start of the file was reached, the first line read is only partial
+    let skipped = leading.len().saturating_sub(amount);
+    Ok(leading.split_off(skipped))
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Reads a shared file from its own offset, without moving a common cursor,
+/// so every range thread can read through the same handle at once.
+struct PositionedReader<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_bytes = read_at(self.file, buf, self.offset)?;
+        self.offset += read_bytes as u64;
+        Ok(read_bytes)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grep_seq;
+    use crate::query::SearchOptions;
+
+    fn search_file(
+        file_name: &str,
+        range_size: u64,
+        query: &Query,
+    ) -> Result<Vec<Match>, GrepError> {
+        let mut matches = vec![];
+        let thread_amount = default_thread_amount();
+        let cancel = CancelToken::new();
+        search_file_ranges(
+            file_name,
+            range_size,
+            thread_amount,
+            query,
+            &cancel,
+            |range| matches.extend(range),
+        )?;
+        Ok(matches)
+    }
+
+    #[test]
+    fn tiny_ranges_find_the_same_matches_as_sequential() {
+        let expected = grep_seq("i", vec!["resources/test1.txt".to_string()]).matches;
+
+        for range_size in [1, 2, 7, 19, 20, 21, 64, 1024] {
+            let result = search_file("resources/test1.txt", range_size, &"i".into());
+            assert_eq!(result.unwrap(), expected, "range size {}", range_size);
+        }
+    }
+
+    #[test]
+    fn context_crosses_range_boundaries() {
+        let query = Query::new(
+            "i",
+            SearchOptions {
+                before_context: 2,
+                after_context: 2,
+                ..SearchOptions::default()
+            },
+        );
+        let expected = grep_seq(query.clone(), vec!["resources/test1.txt".to_string()]).matches;
+
+        for range_size in [1, 2, 7, 19, 20, 21, 64, 1024] {
+            let result = search_file("resources/test1.txt", range_size, &query);
+            assert_eq!(result.unwrap(), expected, "range size {}", range_size);
+        }
+    }
+
+    #[test]
+    fn latin1_lines_keep_global_line_numbers() {
+        let result = search_file("resources/latin1.txt", 4, &"file".into()).unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].line_number, 2);
+        assert_eq!(result[0].line, "Café au lait, file");
+    }
+}
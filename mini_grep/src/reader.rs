@@ -0,0 +1,162 @@
+use crate::cancel::CancelToken;
+use crate::error::GrepError;
+use crate::lines::numbered_lines;
+use crate::pooling::WorkerPool;
+use crate::query::Query;
+use crate::results::SearchResults;
+use crate::stealing::StealingPool;
+use crate::stream::{collect_outcomes, Delivery, MatchStream, Outcome};
+use crate::{queue_chunk_tasks, scan_chunks, scan_lines, Job, Strategy, DEFAULT_CHUNK_SIZE};
+use std::io::{BufReader, Read};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+
+/// Searches everything `reader` yields, like standard input or a pipe, reporting
+/// its lines as coming from `name`.
+///
+/// A reader can only be read once, from its start, so strategies that split files
+/// read it on the calling thread and hand its chunks to workers as they come:
+/// `Chunked` and `Ranges` start a thread per chunk, and `Pool` and `Stealing` queue
+/// them on their pool.
+/// `Sequential` and `PerFile` scan it on the calling thread.
+pub fn search_reader(
+    strategy: Strategy,
+    query: impl Into<Query>,
+    name: &str,
+    reader: impl Read,
+) -> SearchResults {
+    let query = Arc::new(query.into());
+    let name: Arc<str> = Arc::from(name);
+    let (tx, rx) = channel::<Outcome>();
+
+    let job = Job::new(
+        query.clone(),
+        tx,
+        CancelToken::new(),
+        DEFAULT_CHUNK_SIZE,
+        None,
+    );
+    scan_reader(strategy, name.clone(), reader, job);
+
+    collect_outcomes(&[name], rx, &query)
+}
+
+/// Like `search_reader`, handing out matches while the reader is still being searched.
+pub fn stream_reader(
+    strategy: Strategy,
+    query: impl Into<Query>,
+    name: &str,
+    reader: impl Read + Send + 'static,
+    delivery: Delivery,
+) -> MatchStream {
+    stream_reader_cancellable(strategy, query, name, reader, delivery, &CancelToken::new())
+}
+
+/// Like `stream_reader`, stopping once `cancel` is cancelled. A reader blocked
+/// waiting for input only notices once it gets some, or reaches its end.
+pub fn stream_reader_cancellable(
+    strategy: Strategy,
+    query: impl Into<Query>,
+    name: &str,
+    reader: impl Read + Send + 'static,
+    delivery: Delivery,
+    cancel: &CancelToken,
+) -> MatchStream {
+    let query = Arc::new(query.into());
+    let name: Arc<str> = Arc::from(name);
+    let (tx, rx) = channel::<Outcome>();
+    // Dropping the stream only cancels its own search, not whatever else `cancel` stops
+    let cancel = cancel.child();
+
+    let job = Job::new(query.clone(), tx, cancel.clone(), DEFAULT_CHUNK_SIZE, None);
+    let name_clone = name.clone();
+    let search = thread::spawn(move || scan_reader(strategy, name_clone, reader, job));
+
+    MatchStream::new(vec![name], &query, delivery, rx, search, cancel)
+}
+
+fn scan_reader(strategy: Strategy, name: Arc<str>, reader: impl Read, job: Job) {
+    let lines = numbered_lines(
+        BufReader::new(reader),
+        name.clone(),
+        job.query.options.decompress,
+    )
+    .map_err(|err| GrepError::from_io(&name, 0, err));
+
+    match strategy {
+        Strategy::Sequential | Strategy::PerFile => scan_lines(0, &name, lines, &job),
+        // Ranges are read on their own from an offset, which a reader cannot seek to
+        Strategy::Chunked | Strategy::Ranges => scan_chunks(0, name, lines, job),
+        Strategy::Pool { thread_amount } => {
+            let pool = WorkerPool::new(thread_amount);
+            queue_chunk_tasks(0, name, lines, pool.spawner(), thread_amount, job);
+            pool.join();
+        }
+        Strategy::Stealing { thread_amount } => {
+            let pool = StealingPool::new(thread_amount);
+            queue_chunk_tasks(0, name, lines, pool.spawner(), thread_amount, job.clone());
+            job.report_scheduler(pool.join());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grep_seq;
+    use crate::lines::NumberedLines;
+    use crate::query::SearchOptions;
+    use crate::tests::STRATEGIES;
+    use std::fs::File;
+
+    #[test]
+    fn readers_find_what_files_do() {
+        let query = Query::new(
+            "i",
+            SearchOptions {
+                before_context: 1,
+                after_context: 1,
+                ..SearchOptions::default()
+            },
+        );
+        let expected = grep_seq(query.clone(), vec!["resources/test1.txt".to_string()]);
+
+        for strategy in STRATEGIES {
+            let file = File::open("resources/test1.txt").unwrap();
+            let result = search_reader(strategy, query.clone(), "resources/test1.txt", file);
+            assert_eq!(result.matches, expected.matches, "{:?}", strategy);
+
+            let text = std::fs::read("resources/test1.txt").unwrap();
+            let streamed = stream_reader(
+                strategy,
+                query.clone(),
+                "resources/test1.txt",
+                std::io::Cursor::new(text),
+                Delivery::InOrder,
+            );
+            let streamed = streamed.collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(streamed, expected.matches, "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn reader_chunks_are_scanned_while_reading() {
+        let text = (1..=50)
+            .map(|n| format!("line {}\n", n))
+            .collect::<String>();
+        let (tx, rx) = channel();
+        let query = Arc::new(Query::from("5"));
+        let lines = Ok(NumberedLines::new(text.as_bytes(), "stdin".into()));
+
+        let job = Job::new(query.clone(), tx, CancelToken::new(), 3, None);
+        scan_chunks(0, "stdin".into(), lines, job);
+        let result = collect_outcomes(&["stdin".into()], rx, &query);
+
+        let line_numbers = result.matches.iter().map(|found| found.line_number);
+        assert_eq!(
+            line_numbers.collect::<Vec<_>>(),
+            vec![5, 15, 25, 35, 45, 50]
+        );
+    }
+}
@@ -0,0 +1,498 @@
+use crate::encoding::{is_utf16, UTF8_BOM};
+use crate::error::GrepError;
+use crate::gzip::is_gzip;
+use crate::matches::Match;
+use crate::pooling::WorkerPool;
+use crate::query::Query;
+use crate::stealing::StealingPool;
+use crate::{search, Strategy};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+
+/// What matches are replaced with. `$1` or `${1}` stand for what a numbered group
+/// captured, `$name` or `${name}` for a named one, `$0` for the whole match and `$$`
+/// for a dollar sign, like `Regex::replace_all` does. Groups that did not take part
+/// in a match are replaced with nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replacement {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Group(GroupRef),
+}
+
+/// A capture group a replacement refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum GroupRef {
+    Index(usize),
+    Name(String),
+}
+
+/// A line a replacement changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineChange {
+    pub line_number: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// Every line a replacement changed in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChanges {
+    pub path: String,
+    pub changes: Vec<LineChange>,
+}
+
+/// What a replacement changed, plus the files that could not be searched or written.
+#[derive(Debug, Default)]
+pub struct ReplaceResults {
+    /// Only files with at least one changed line, in the order they were given.
+    pub files: Vec<FileChanges>,
+    pub errors: Vec<GrepError>,
+}
+
+impl Replacement {
+    pub fn new(template: &str) -> Replacement {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut rest = template;
+
+        while let Some(dollar) = rest.find('$') {
+            text.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+
+            let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => ("", rest),
+                }
+            } else {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            };
+
+            if name.is_empty() {
+                // `$$` is an escaped dollar, and a lone one is kept as it is
+                text.push('$');
+                rest = rest.strip_prefix('$').unwrap_or(rest);
+                continue;
+            }
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(Part::Group(match name.parse() {
+                Ok(index) => GroupRef::Index(index),
+                Err(_) => GroupRef::Name(name.to_string()),
+            }));
+            rest = after;
+        }
+
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Replacement { parts }
+    }
+
+    /// Appends the replacement of a match to `out`, looking up what its groups captured.
+    pub(crate) fn expand<'a>(
+        &self,
+        out: &mut String,
+        group: impl Fn(&GroupRef) -> Option<&'a str>,
+    ) {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Group(reference) => out.push_str(group(reference).unwrap_or("")),
+            }
+        }
+    }
+}
+
+impl From<&str> for Replacement {
+    fn from(template: &str) -> Self {
+        Replacement::new(template)
+    }
+}
+
+impl ReplaceResults {
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn changed_lines(&self) -> usize {
+        self.files.iter().map(|file| file.changes.len()).sum()
+    }
+}
+
+/// Replaces every match of `query` in the given files, returning the lines it changed.
+///
+/// Matches are found with `strategy`, like `search` does. Files are then handled on
+/// the threads the strategy would use for them: each one on its own, or on the pool.
+/// When `in_place` is set, each file is rewritten into a temporary file next to it,
+/// which then takes its place, so a file is never left half written.
+pub fn replace(
+    strategy: Strategy,
+    query: impl Into<Query>,
+    replacement: impl Into<Replacement>,
+    file_names: Vec<String>,
+    in_place: bool,
+) -> ReplaceResults {
+    let mut query = query.into();
+    // A match has to be a part of a single line to be replaced in it
+    query.options.multiline = None;
+    let query = Arc::new(query);
+    let replacement = Arc::new(replacement.into());
+
+    // Rewriting a file listed twice would replace what was already replaced
+    let mut seen = HashSet::new();
+    let file_names = file_names
+        .into_iter()
+        .filter(|file_name| seen.insert(file_name.clone()))
+        .collect::<Vec<_>>();
+
+    let found = search(strategy, query.as_ref().clone(), file_names.clone());
+    let mut results = ReplaceResults {
+        files: vec![],
+        errors: found.errors,
+    };
+
+    let mut matches_per_file: Vec<Vec<Match>> = file_names.iter().map(|_| vec![]).collect();
+    let mut file = 0;
+    for found in found.matches {
+        while file_names[file] != found.path {
+            file += 1;
+        }
+        matches_per_file[file].push(found);
+    }
+
+    let (tx, rx) = channel();
+    let mut replaced_files = vec![];
+    let mut tasks = vec![];
+    for (file, (file_name, matches)) in file_names.into_iter().zip(matches_per_file).enumerate() {
+        if matches.is_empty() {
+            continue;
+        }
+        replaced_files.push((file, file_name.clone()));
+        let (query, replacement, tx) = (query.clone(), replacement.clone(), tx.clone());
+        tasks.push(move || {
+            let changes = replace_file(file_name, matches, &query, &replacement, in_place);
+            let _ = tx.send((file, changes));
+        });
+    }
+    drop(tx);
+    run_tasks(strategy, tasks);
+
+    let mut replaced = rx.into_iter().collect::<HashMap<_, _>>();
+    for (file, file_name) in replaced_files {
+        // A worker that panicked never reported back
+        match replaced.remove(&file) {
+            Some(Ok(changes)) => results.files.push(changes),
+            Some(Err(err)) => results.errors.push(err),
+            None => results.errors.push(GrepError::WorkerPanic(file_name)),
+        }
+    }
+    results
+}
+
+/// Runs the work of every file on the threads `strategy` would search it with.
+fn run_tasks(strategy: Strategy, tasks: Vec<impl FnOnce() + Send + 'static>) {
+    match strategy {
+        Strategy::Sequential => tasks.into_iter().for_each(|task| task()),
+        Strategy::PerFile | Strategy::Chunked | Strategy::Ranges => {
+            let threads = tasks.into_iter().map(thread::spawn).collect::<Vec<_>>();
+            threads.into_iter().for_each(|t| {
+                let _ = t.join();
+            });
+        }
+        Strategy::Pool { thread_amount } => {
+            let pool = WorkerPool::new(thread_amount);
+            tasks.into_iter().for_each(|task| pool.spawn(task));
+            pool.join();
+        }
+        Strategy::Stealing { thread_amount } => {
+            let pool = StealingPool::new(thread_amount);
+            tasks.into_iter().for_each(|task| pool.spawn(task));
+            pool.join();
+        }
+    }
+}
+
+fn replace_file(
+    file_name: String,
+    matches: Vec<Match>,
+    query: &Query,
+    replacement: &Replacement,
+    in_place: bool,
+) -> Result<FileChanges, GrepError> {
+    let changes = matches
+        .into_iter()
+        .filter_map(|found| {
+            let after = query
+                .pattern
+                .replace_all(&found.line, replacement)
+                .into_owned();
+            (after != found.line).then_some(LineChange {
+                line_number: found.line_number,
+                before: found.line,
+                after,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if in_place && !changes.is_empty() {
+        write_in_place(&file_name, &changes)?;
+    }
+    Ok(FileChanges {
+        path: file_name,
+        changes,
+    })
+}
+
+/// Copies the file into a temporary one next to it, changing the given lines, and
+/// renames it over the original, which keeps its permissions. Line endings are kept.
+fn write_in_place(file_name: &str, changes: &[LineChange]) -> Result<(), GrepError> {
+    let path = Path::new(file_name);
+    let temporary = path.with_file_name(format!(
+        ".{}.mini_grep-{}.tmp",
+        path.file_name()
+            .map_or("".into(), |name| name.to_string_lossy()),
+        std::process::id()
+    ));
+
+    let written = copy_with_changes(file_name, &temporary, changes).and_then(|()| {
+        fs::metadata(path)
+            .and_then(|metadata| fs::set_permissions(&temporary, metadata.permissions()))
+            .and_then(|()| fs::rename(&temporary, path))
+            .map_err(|err| GrepError::from_io(file_name, 0, err))
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
+/// Lines that were transcoded to be searched are not written back, whether the file
+/// is UTF-16 or only some of its lines are not UTF-8, and neither are compressed
+/// files. A UTF-8 byte order mark, which the first line was searched without, is kept.
+fn copy_with_changes(
+    file_name: &str,
+    temporary: &Path,
+    changes: &[LineChange],
+) -> Result<(), GrepError> {
+    let to_grep_error = |err| GrepError::from_io(file_name, 0, err);
+    if is_gzip(file_name) {
+        return Err(to_grep_error(io::Error::other(
+            "Compressed files cannot be replaced in",
+        )));
+    }
+    let mut reader = BufReader::new(File::open(file_name).map_err(to_grep_error)?);
+    let mut writer = BufWriter::new(File::create(temporary).map_err(to_grep_error)?);
+    let is_utf16 = is_utf16(reader.fill_buf().map_err(to_grep_error)?);
+    let mut changes = changes.iter().peekable();
+    let mut line = vec![];
+    let mut line_number = 0;
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).map_err(to_grep_error)? == 0 {
+            break;
+        }
+        line_number += 1;
+
+        match changes.next_if(|change| change.line_number == line_number) {
+            Some(change) => {
+                let ending = line_ending(&line);
+                let bom = match line_number == 1 && line.starts_with(UTF8_BOM.as_bytes()) {
+                    true => UTF8_BOM.as_bytes(),
+                    false => &[],
+                };
+                let before = &line[bom.len()..line.len() - ending.len()];
+                if is_utf16 || std::str::from_utf8(before).is_err() {
+                    return Err(GrepError::InvalidUtf8 {
+                        path: file_name.to_string(),
+                        line_number,
+                    });
+                }
+                // The file may have changed since it was searched
+                if before != change.before.as_bytes() {
+                    return Err(to_grep_error(io::Error::other(
+                        "File changed while replacing it",
+                    )));
+                }
+                writer
+                    .write_all(bom)
+                    .and_then(|()| writer.write_all(change.after.as_bytes()))
+                    .and_then(|()| writer.write_all(ending))
+                    .map_err(to_grep_error)?;
+            }
+            None => writer.write_all(&line).map_err(to_grep_error)?,
+        }
+    }
+    if changes.next().is_some() {
+        return Err(to_grep_error(io::Error::other(
+            "File changed while replacing it",
+        )));
+    }
+
+    writer
+        .into_inner()
+        .map_err(|err| to_grep_error(err.into_error()))?
+        .sync_all()
+        .map_err(to_grep_error)
+}
+
+fn line_ending(line: &[u8]) -> &[u8] {
+    if line.ends_with(b"\r\n") {
+        &line[line.len() - 2..]
+    } else if line.ends_with(b"\n") {
+        &line[line.len() - 1..]
+    } else {
+        &[]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+    use crate::query::SearchOptions;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn templates_refer_to_groups_by_index_and_name() {
+        let pattern = Pattern::regex(r"(?P<key>\w+)=(\d+)").unwrap();
+        let replace = |template| pattern.replace_all("a=1, b=22", &Replacement::new(template));
+
+        assert_eq!(replace("$2=${key}"), "1=a, 22=b");
+        assert_eq!(replace("[$0]"), "[a=1], [b=22]");
+        assert_eq!(replace("$$1 $9 ${2}x"), "$1  1x, $1  22x");
+    }
+
+    #[test]
+    fn groups_belong_to_the_expression_that_matched() {
+        let pattern = Pattern::with_options(
+            &[r"(\d+) apples", r"(\w+) pears"],
+            &crate::PatternOptions::default(),
+        )
+        .unwrap();
+
+        let replaced = pattern.replace_all("3 apples, ripe pears", &Replacement::new("<$1>"));
+        assert_eq!(replaced, "<3>, <ripe>");
+
+        let literals = Pattern::with_options(&["apples", "pears"], &Default::default()).unwrap();
+        let replaced = literals.replace_all("3 apples, ripe pears", &Replacement::new("$0!"));
+        assert_eq!(replaced, "3 apples!, ripe pears!");
+    }
+
+    #[test]
+    fn in_place_replacement_keeps_line_endings() {
+        let dir = std::env::temp_dir().join(format!("mini_grep_replace_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        fs::write(&path, "token=abc\r\nkeep\ntoken=xyz").unwrap();
+        let file_names = vec![path.to_string_lossy().into_owned()];
+
+        for strategy in [Strategy::Sequential, Strategy::Pool { thread_amount: 2 }] {
+            let query = Pattern::regex(r"token=(\w+)").unwrap();
+            let preview = replace(strategy, query, "token=***", file_names.clone(), false);
+            assert_eq!(preview.changed_lines(), 2);
+            assert_eq!(preview.files[0].changes[1].after, "token=***");
+            assert_eq!(
+                fs::read_to_string(&path).unwrap(),
+                "token=abc\r\nkeep\ntoken=xyz"
+            );
+        }
+
+        let query = Pattern::regex(r"token=(\w+)").unwrap();
+        let written = replace(
+            Strategy::Chunked,
+            query,
+            "token=<$1>",
+            file_names.clone(),
+            true,
+        );
+        assert!(!written.has_errors(), "{:?}", written.errors);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "token=<abc>\r\nkeep\ntoken=<xyz>"
+        );
+
+        // Multiline queries are still replaced line by line
+        let query = crate::SearchConfig::new("<(x+)")
+            .multiline(Some(2))
+            .query()
+            .unwrap();
+        let written = replace(Strategy::Sequential, query, "<$1$1", file_names, true);
+        assert!(!written.has_errors(), "{:?}", written.errors);
+        assert!(fs::read_to_string(&path).unwrap().ends_with("token=<xxyz>"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transcoded_lines_are_not_replaced_in() {
+        let dir = std::env::temp_dir().join(format!("mini_grep_latin1_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("menu.txt");
+        fs::write(&path, b"token=abc\ncaf\xe9 token=xyz\n").unwrap();
+        let file_names = vec![path.to_string_lossy().into_owned()];
+
+        let query = Pattern::regex(r"token=(\w+)").unwrap();
+        let written = replace(Strategy::Sequential, query, "token=***", file_names, true);
+        assert!(matches!(
+            written.errors[..],
+            [GrepError::InvalidUtf8 { line_number: 2, .. }]
+        ));
+        assert_eq!(fs::read(&path).unwrap(), b"token=abc\ncaf\xe9 token=xyz\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn byte_order_marks_are_kept_and_compressed_files_left_as_they_are() {
+        let dir = std::env::temp_dir().join(format!("mini_grep_bom_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let marked = dir.join("marked.txt");
+        fs::write(&marked, "\u{feff}hello\nhello\n").unwrap();
+        let compressed = dir.join("compressed.txt.gz");
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"hello\n").unwrap();
+        let gzip = encoder.finish().unwrap();
+        fs::write(&compressed, &gzip).unwrap();
+        let file_names = [&marked, &compressed].map(|path| path.to_string_lossy().into_owned());
+
+        let query = Query::new(
+            "hello",
+            SearchOptions {
+                decompress: true,
+                ..SearchOptions::default()
+            },
+        );
+        let written = replace(
+            Strategy::Sequential,
+            query,
+            "bye",
+            file_names.to_vec(),
+            true,
+        );
+
+        assert_eq!(written.changed_lines(), 2);
+        assert_eq!(fs::read_to_string(&marked).unwrap(), "\u{feff}bye\nbye\n");
+        assert_eq!(written.errors.len(), 1);
+        assert_eq!(written.errors[0].path(), file_names[1]);
+        assert_eq!(fs::read(&compressed).unwrap(), gzip);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use crate::error::GrepError;
+use crate::matches::Match;
+use crate::stealing::SchedulerReport;
+
+/// Everything a search found, plus the files that could not be searched.
+#[derive(Debug, Default)]
+pub struct SearchResults {
+    pub matches: Vec<Match>,
+    pub errors: Vec<GrepError>,
+    /// How busy the workers were, for work-stealing searches.
+    pub scheduler_reports: Vec<SchedulerReport>,
+}
+
+impl SearchResults {
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+}
+
+/// Collects per-file outcomes, keeping the order in which files were given.
+impl FromIterator<Result<Vec<Match>, GrepError>> for SearchResults {
+    fn from_iter<T: IntoIterator<Item = Result<Vec<Match>, GrepError>>>(iter: T) -> Self {
+        let mut results = SearchResults::default();
+        for file_result in iter {
+            match file_result {
+                Ok(matches) => results.matches.extend(matches),
+                Err(err) => results.errors.push(err),
+            }
+        }
+        results
+    }
+}
@@ -0,0 +1,206 @@
+use crate::stealing::SchedulerReport;
+use crate::{
+    ranges, run_pool, run_stealing, scan_file, scan_file_chunks, spawn_file_threads, Job, Strategy,
+};
+use std::sync::{Arc, Mutex};
+
+/// Lines in each chunk unless a `SearchConfig` says otherwise.
+pub const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+/// Splits a search between threads. The strategies of this crate implement it,
+/// and so can anything else that wants to schedule the files its own way.
+pub trait Searcher: Send + Sync {
+    /// Scans every file, telling `job` what each one holds, and returns once all of
+    /// them are over. Files are numbered by their position in `file_names`.
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job);
+}
+
+/// One file after another, on a single thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sequential;
+
+/// One thread per file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PerFile;
+
+/// One thread per file, plus one per chunk of lines of that file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Chunked;
+
+/// One thread per file, plus one per CPU reading and scanning byte ranges of that file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ranges;
+
+/// Files and chunks of lines taken by a fixed amount of threads.
+#[derive(Debug, Clone, Copy)]
+pub struct Pool {
+    pub thread_amount: usize,
+}
+
+/// Files and chunks of lines queued on the deque of the worker that read them, where
+/// idle workers steal them from. Clones share the reports of the searches they ran.
+#[derive(Debug, Clone)]
+pub struct WorkStealing {
+    thread_amount: usize,
+    reports: Arc<Mutex<Vec<SchedulerReport>>>,
+}
+
+impl WorkStealing {
+    pub fn new(thread_amount: usize) -> WorkStealing {
+        WorkStealing {
+            thread_amount,
+            reports: Arc::default(),
+        }
+    }
+
+    /// How busy every worker was, for each search that ran so far, in order.
+    pub fn reports(&self) -> Vec<SchedulerReport> {
+        self.reports
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+impl Searcher for Sequential {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        for (file, file_name) in file_names.into_iter().enumerate() {
+            job.scan_file(file, file_name);
+        }
+    }
+}
+
+impl Searcher for PerFile {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        spawn_file_threads(file_names, job, scan_file);
+    }
+}
+
+impl Searcher for Chunked {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        spawn_file_threads(file_names, job, scan_file_chunks);
+    }
+}
+
+impl Searcher for Ranges {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        spawn_file_threads(file_names, job, ranges::scan_file_ranges);
+    }
+}
+
+impl Searcher for Pool {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        let thread_amount = job.thread_amount().unwrap_or(self.thread_amount);
+        run_pool(file_names, job, thread_amount);
+    }
+}
+
+impl Searcher for WorkStealing {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        let thread_amount = job.thread_amount().unwrap_or(self.thread_amount);
+        let report = run_stealing(file_names, job, thread_amount);
+        self.reports
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(report);
+    }
+}
+
+impl Searcher for Strategy {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        match *self {
+            Strategy::Sequential => Sequential.scan(file_names, job),
+            Strategy::PerFile => PerFile.scan(file_names, job),
+            Strategy::Chunked => Chunked.scan(file_names, job),
+            Strategy::Ranges => Ranges.scan(file_names, job),
+            Strategy::Pool { thread_amount } => Pool { thread_amount }.scan(file_names, job),
+            Strategy::Stealing { thread_amount } => {
+                WorkStealing::new(thread_amount).scan(file_names, job)
+            }
+        }
+    }
+}
+
+impl<S: Searcher + ?Sized> Searcher for Box<S> {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        (**self).scan(file_names, job)
+    }
+}
+
+impl<S: Searcher + ?Sized> Searcher for Arc<S> {
+    fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+        (**self).scan(file_names, job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grep_seq, grep_stream, search, Delivery, GrepError, SearchEvent};
+    use std::thread;
+
+    /// Scans the files last to first, on two threads, and fails the ones it does not like.
+    struct Backwards;
+
+    impl Searcher for Backwards {
+        fn scan(&self, file_names: Vec<Arc<str>>, job: Job) {
+            let files = file_names.into_iter().enumerate().rev().collect::<Vec<_>>();
+            let (even, odd): (Vec<_>, Vec<_>) =
+                files.into_iter().partition(|(file, _)| file % 2 == 0);
+            thread::scope(|scope| {
+                for files in [even, odd] {
+                    let job = job.clone();
+                    scope.spawn(move || {
+                        for (file, file_name) in files {
+                            if file_name.ends_with(".md") {
+                                let err = GrepError::Cancelled(file_name.to_string());
+                                job.report_done(file, &file_name, 0, Err(err));
+                            } else {
+                                job.scan_file_chunks(file, file_name);
+                            }
+                        }
+                    });
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn third_party_searchers_report_like_the_builtin_ones() {
+        let file_names = vec![
+            "resources/test1.txt".to_string(),
+            "resources/latin1.txt".to_string(),
+            "notes.md".to_string(),
+            "resources/test1.txt".to_string(),
+        ];
+        let mut searched = file_names.clone();
+        searched.remove(2);
+        let expected = grep_seq("i", searched);
+
+        let result = search(Box::new(Backwards) as Box<dyn Searcher>, "i", file_names);
+
+        assert_eq!(result.matches, expected.matches);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].path(), "notes.md");
+    }
+
+    #[test]
+    fn stealing_strategies_report_like_work_stealing_searchers() {
+        let file_names = vec!["resources/test1.txt".to_string(); 2];
+        let strategy = Strategy::Stealing { thread_amount: 2 };
+
+        let stealing = WorkStealing::new(2);
+        let result = search(stealing.clone(), "i", file_names.clone());
+        assert_eq!(result.scheduler_reports, stealing.reports());
+        let result = search(strategy, "i", file_names.clone());
+        assert_eq!(result.scheduler_reports.len(), 1);
+        assert_eq!(result.scheduler_reports[0].workers.len(), 2);
+
+        let events = grep_stream(strategy, "i", file_names, Delivery::InOrder)
+            .events()
+            .collect::<Vec<_>>();
+        assert!(
+            matches!(events.last(), Some(SearchEvent::Scheduled(report)) if report.workers.len() == 2)
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::pooling::{Spawn, Task};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Fixed amount of threads, each with a deque of its own. Tasks queued by a worker
+/// go to its own deque, and it takes the newest one first. A worker whose deque is
+/// empty steals the oldest task of another one, so the chunks a single worker
+/// queued for a big file spread over every idle worker.
+pub(crate) struct StealingPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<WorkerReport>>,
+    started: Instant,
+}
+
+/// Handle used to queue tasks, which tasks themselves may hold to queue more work.
+#[derive(Clone)]
+pub(crate) struct StealingSpawner(Arc<Shared>);
+
+struct Shared {
+    deques: Vec<Mutex<VecDeque<Task>>>,
+    state: Mutex<State>,
+    /// Wakes up idle workers when a task is queued, and everyone once the pool is over.
+    wake: Condvar,
+    /// Deque that the next task queued from outside the pool goes to.
+    next_deque: AtomicUsize,
+}
+
+struct State {
+    /// Tasks queued or running. Running tasks may still queue more.
+    pending: usize,
+    /// Bumped whenever a task is queued, so a worker about to sleep can tell that one
+    /// was queued after it last looked at the deques.
+    queued: u64,
+    /// No task is queued from outside the pool anymore.
+    closed: bool,
+}
+
+/// What one worker of a work-stealing search did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerReport {
+    /// Tasks it ran, stolen ones included.
+    pub tasks: usize,
+    /// Tasks it took from the deque of another worker.
+    pub stolen: usize,
+    /// Time spent running tasks, as opposed to looking for them or sleeping.
+    pub busy: Duration,
+}
+
+/// How busy every worker of a work-stealing search was, to tell how well balanced it was.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchedulerReport {
+    pub workers: Vec<WorkerReport>,
+    pub elapsed: Duration,
+}
+
+thread_local! {
+    /// The pool the current thread works for, if any, and its index there.
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+impl StealingPool {
+    pub fn new(thread_amount: usize) -> StealingPool {
+        let thread_amount = thread_amount.max(1);
+        let shared = Arc::new(Shared {
+            deques: (0..thread_amount)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            state: Mutex::new(State {
+                pending: 0,
+                queued: 0,
+                closed: false,
+            }),
+            wake: Condvar::new(),
+            next_deque: AtomicUsize::new(0),
+        });
+
+        let workers = (0..thread_amount)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::spawn(move || run_worker(shared, index))
+            })
+            .collect();
+
+        StealingPool {
+            shared,
+            workers,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn spawner(&self) -> StealingSpawner {
+        StealingSpawner(self.shared.clone())
+    }
+
+    pub fn spawn(&self, task: impl Send + FnOnce() + 'static) {
+        self.spawner().spawn(task);
+    }
+
+    /// Waits for every queued task, including the ones queued by other tasks,
+    /// and tells how busy each worker was.
+    pub fn join(self) -> SchedulerReport {
+        self.shared.lock_state().closed = true;
+        self.shared.wake.notify_all();
+
+        let workers = self
+            .workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect();
+        SchedulerReport {
+            workers,
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+impl Spawn for StealingSpawner {
+    fn spawn(&self, task: impl Send + FnOnce() + 'static) {
+        let shared = &self.0;
+        let deque = match CURRENT_WORKER.get() {
+            Some((pool, index)) if pool == shared.id() => index,
+            _ => shared.next_deque.fetch_add(1, Ordering::Relaxed) % shared.deques.len(),
+        };
+        // Counted before it can be taken, so finishing it never makes `pending` wrap around
+        let mut state = shared.lock_state();
+        state.pending += 1;
+        state.queued += 1;
+        shared.deques[deque]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push_back(Box::new(task));
+        drop(state);
+        shared.wake.notify_one();
+    }
+}
+
+impl Shared {
+    fn id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The newest task of the worker's own deque, or else the oldest one of
+    /// another deque, which is the one its owner would get to last.
+    fn find_task(&self, index: usize) -> Option<(Task, bool)> {
+        let lock = |deque: usize| {
+            self.deques[deque]
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        };
+        if let Some(task) = lock(index).pop_back() {
+            return Some((task, false));
+        }
+        (1..self.deques.len())
+            .map(|offset| (index + offset) % self.deques.len())
+            .find_map(|victim| lock(victim).pop_front())
+            .map(|task| (task, true))
+    }
+}
+
+fn run_worker(shared: Arc<Shared>, index: usize) -> WorkerReport {
+    CURRENT_WORKER.set(Some((shared.id(), index)));
+    let mut report = WorkerReport::default();
+
+    loop {
+        let queued = shared.lock_state().queued;
+
+        if let Some((task, stolen)) = shared.find_task(index) {
+            let started = Instant::now();
+            // A panicking task must not take its worker down with it: whoever
+            // waits for that task's result notices it is missing instead.
+            let _ = panic::catch_unwind(AssertUnwindSafe(task));
+            report.busy += started.elapsed();
+            report.tasks += 1;
+            report.stolen += stolen as usize;
+
+            let mut state = shared.lock_state();
+            state.pending -= 1;
+            if state.closed && state.pending == 0 {
+                shared.wake.notify_all();
+            }
+            continue;
+        }
+
+        let mut state = shared.lock_state();
+        while state.queued == queued && !(state.closed && state.pending == 0) {
+            state = shared
+                .wake
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        if state.closed && state.pending == 0 {
+            CURRENT_WORKER.set(None);
+            return report;
+        }
+    }
+}
+
+impl SchedulerReport {
+    /// Share of the search a worker spent running tasks, from 0 to 1.
+    pub fn utilization(&self, worker: usize) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.workers[worker].busy.as_secs_f64() / self.elapsed.as_secs_f64()
+    }
+}
+
+impl Display for SchedulerReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, worker) in self.workers.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "worker {}: {:.0}% busy, {} tasks ({} stolen)",
+                index,
+                self.utilization(index) * 100.0,
+                worker.tasks,
+                worker.stolen
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn idle_workers_steal_what_a_busy_one_queued() {
+        let pool = StealingPool::new(3);
+        let (tx, rx) = channel();
+
+        // A single task queues every chunk on its own worker, like a file being read
+        let spawner = pool.spawner();
+        pool.spawn(move || {
+            for chunk in 0..30 {
+                let tx = tx.clone();
+                spawner.spawn(move || {
+                    thread::sleep(Duration::from_millis(2));
+                    tx.send(chunk).unwrap();
+                });
+            }
+            spawner.spawn(|| panic!("chunk failed"));
+        });
+        let report = pool.join();
+
+        let mut chunks = rx.into_iter().collect::<Vec<_>>();
+        chunks.sort_unstable();
+        assert_eq!(chunks, (0..30).collect::<Vec<_>>());
+        assert_eq!(
+            report
+                .workers
+                .iter()
+                .map(|worker| worker.tasks)
+                .sum::<usize>(),
+            32
+        );
+        assert!(
+            report
+                .workers
+                .iter()
+                .map(|worker| worker.stolen)
+                .sum::<usize>()
+                > 0
+        );
+        assert!((0..3).all(|worker| report.utilization(worker) <= 1.0));
+    }
+}
@@ -0,0 +1,441 @@
+use crate::cancel::CancelToken;
+use crate::error::GrepError;
+use crate::filter::merge_pieces;
+use crate::matches::Match;
+use crate::query::Query;
+use crate::results::SearchResults;
+use crate::stealing::SchedulerReport;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// What workers report about a file: the matches of every piece of it they
+/// scanned, plus once how many pieces there were and whether reading it failed.
+pub(crate) enum Outcome {
+    Piece {
+        file: usize,
+        piece: usize,
+        matches: Vec<Match>,
+    },
+    FileDone {
+        file: usize,
+        pieces: usize,
+        result: Result<(), GrepError>,
+    },
+    Scheduled(SchedulerReport),
+}
+
+/// Order in which a `MatchStream` hands out what the workers find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Delivery {
+    /// The same order a batch search reports: file by file, line by line.
+    #[default]
+    InOrder,
+    /// As soon as a worker reports it. Lines of a file still come in order when
+    /// there is a per-file limit, since it decides which of them are reported.
+    AsFound,
+}
+
+/// Something a running search reports about a file. Every file begins, then
+/// reports its matches, then ends, although files may interleave when delivered
+/// as found.
+#[derive(Debug)]
+pub enum SearchEvent {
+    Begin {
+        path: String,
+    },
+    Match(Match),
+    End {
+        path: String,
+        matches: usize,
+        /// Why the file could not be searched, or not to its end.
+        error: Option<GrepError>,
+    },
+    /// How busy the workers of a work-stealing search were, once every file is over.
+    Scheduled(SchedulerReport),
+}
+
+/// Matches of a search that is still running, handed out as workers find them.
+/// Errors come next to the matches, once the file they belong to is over, so
+/// a file that fails halfway may have reported some matches already.
+///
+/// Dropping the stream cancels the search, since nobody is left to hand matches to.
+pub struct MatchStream {
+    outcomes: Receiver<Outcome>,
+    sorter: Sorter,
+    search: Option<JoinHandle<()>>,
+    cancel: CancelToken,
+}
+
+/// The same search as a `MatchStream`, also telling where every file begins and ends.
+pub struct SearchEvents(MatchStream);
+
+impl MatchStream {
+    pub(crate) fn new(
+        file_names: Vec<Arc<str>>,
+        query: &Query,
+        delivery: Delivery,
+        outcomes: Receiver<Outcome>,
+        search: JoinHandle<()>,
+        cancel: CancelToken,
+    ) -> MatchStream {
+        MatchStream {
+            outcomes,
+            sorter: Sorter::new(file_names, query.options.max_count, delivery),
+            search: Some(search),
+            cancel,
+        }
+    }
+
+    /// A token that stops this search, for instance from another thread.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    pub fn events(self) -> SearchEvents {
+        SearchEvents(self)
+    }
+
+    fn next_event(&mut self) -> Option<SearchEvent> {
+        loop {
+            if let Some(event) = self.sorter.ready.pop_front() {
+                return Some(event);
+            }
+            let search = self.search.take()?;
+
+            match self.outcomes.recv() {
+                Ok(outcome) => {
+                    self.sorter.accept(outcome);
+                    self.search = Some(search);
+                }
+                Err(_) => {
+                    // Every worker is gone, so whatever was not reported never will be
+                    let _ = search.join();
+                    self.sorter.close();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for MatchStream {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Iterator for MatchStream {
+    type Item = Result<Match, GrepError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_event()? {
+                SearchEvent::Match(found) => return Some(Ok(found)),
+                SearchEvent::End {
+                    error: Some(err), ..
+                } => return Some(Err(err)),
+                SearchEvent::Begin { .. } | SearchEvent::End { .. } | SearchEvent::Scheduled(_) => {
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for SearchEvents {
+    type Item = SearchEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_event()
+    }
+}
+
+/// Turns outcomes arriving in any order into the order a `Delivery` asks for.
+struct Sorter {
+    file_names: Vec<Arc<str>>,
+    max_count: usize,
+    delivery: Delivery,
+    files: Vec<FileProgress>,
+    next_file: usize,
+    ready: VecDeque<SearchEvent>,
+}
+
+#[derive(Default)]
+struct FileProgress {
+    pending: BTreeMap<usize, Vec<Match>>,
+    released_pieces: usize,
+    released_matches: usize,
+    done: Option<(usize, Result<(), GrepError>)>,
+    begun: bool,
+    finished: bool,
+}
+
+impl Sorter {
+    fn new(file_names: Vec<Arc<str>>, max_count: Option<usize>, delivery: Delivery) -> Sorter {
+        Sorter {
+            files: file_names.iter().map(|_| FileProgress::default()).collect(),
+            file_names,
+            max_count: max_count.unwrap_or(usize::MAX),
+            delivery,
+            next_file: 0,
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn accept(&mut self, outcome: Outcome) {
+        let file = match outcome {
+            Outcome::Piece {
+                file,
+                piece,
+                matches,
+            } => {
+                self.files[file].pending.insert(piece, matches);
+                file
+            }
+            Outcome::FileDone {
+                file,
+                pieces,
+                result,
+            } => {
+                self.files[file].done = Some((pieces, result));
+                file
+            }
+            Outcome::Scheduled(report) => {
+                self.ready.push_back(SearchEvent::Scheduled(report));
+                return;
+            }
+        };
+
+        match self.delivery {
+            Delivery::AsFound => self.release(file),
+            Delivery::InOrder => {
+                while self.next_file < self.files.len() {
+                    self.release(self.next_file);
+                    if !self.files[self.next_file].finished {
+                        break;
+                    }
+                    self.next_file += 1;
+                }
+            }
+        }
+    }
+
+    /// Hands out the pieces of a file that can already be, and its error once it is over.
+    fn release(&mut self, file: usize) {
+        let unordered = self.delivery == Delivery::AsFound && self.max_count == usize::MAX;
+        let progress = &mut self.files[file];
+
+        loop {
+            let piece = if unordered {
+                progress.pending.pop_first().map(|(_, matches)| matches)
+            } else {
+                progress.pending.remove(&progress.released_pieces)
+            };
+            let Some(matches) = piece else {
+                break;
+            };
+            progress.released_pieces += 1;
+
+            let room = self.max_count - progress.released_matches;
+            for found in matches.into_iter().take(room) {
+                if !progress.begun {
+                    progress.begun = true;
+                    self.ready.push_back(SearchEvent::Begin {
+                        path: self.file_names[file].to_string(),
+                    });
+                }
+                self.ready.push_back(SearchEvent::Match(found));
+                progress.released_matches += 1;
+            }
+        }
+
+        if let Some((pieces, _)) = &progress.done {
+            if !progress.finished && progress.released_pieces == *pieces {
+                let error = match progress.done.take() {
+                    Some((_, Err(err))) => Some(err),
+                    _ => None,
+                };
+                self.end(file, error);
+            }
+        }
+    }
+
+    fn end(&mut self, file: usize, error: Option<GrepError>) {
+        let path = self.file_names[file].to_string();
+        let progress = &mut self.files[file];
+        progress.finished = true;
+
+        if !progress.begun {
+            progress.begun = true;
+            self.ready
+                .push_back(SearchEvent::Begin { path: path.clone() });
+        }
+        self.ready.push_back(SearchEvent::End {
+            path,
+            matches: progress.released_matches,
+            error,
+        });
+    }
+
+    /// Called once no more outcomes can arrive: files still missing pieces
+    /// had a worker panic while scanning them.
+    fn close(&mut self) {
+        for file in 0..self.files.len() {
+            self.release(file);
+
+            let progress = &mut self.files[file];
+            if progress.finished {
+                continue;
+            }
+            let err = match progress.done.take() {
+                Some((_, Err(err))) => err,
+                _ => GrepError::WorkerPanic(self.file_names[file].to_string()),
+            };
+            self.end(file, Some(err));
+        }
+    }
+}
+
+/// Waits for every outcome and puts pieces back in file order, so the result does
+/// not depend on scheduling. Files that failed only report their error.
+pub(crate) fn collect_outcomes(
+    file_names: &[Arc<str>],
+    outcomes: Receiver<Outcome>,
+    query: &Query,
+) -> SearchResults {
+    let mut piece_matches: Vec<BTreeMap<usize, Vec<Match>>> =
+        vec![BTreeMap::new(); file_names.len()];
+    let mut read_results: Vec<Option<(usize, Result<(), GrepError>)>> =
+        file_names.iter().map(|_| None).collect();
+    let mut scheduler_reports = vec![];
+
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Piece {
+                file,
+                piece,
+                matches,
+            } => {
+                piece_matches[file].insert(piece, matches);
+            }
+            Outcome::FileDone {
+                file,
+                pieces,
+                result,
+            } => read_results[file] = Some((pieces, result)),
+            Outcome::Scheduled(report) => scheduler_reports.push(report),
+        }
+    }
+
+    let mut results: SearchResults = file_names
+        .iter()
+        .zip(piece_matches.into_iter().zip(read_results))
+        .map(|(file_name, (pieces, read_result))| match read_result {
+            Some((_, Err(err))) => Err(err),
+            Some((expected, Ok(()))) if pieces.len() == expected => {
+                Ok(merge_pieces(pieces.into_values(), query))
+            }
+            // A worker that panicked never reported back
+            _ => Err(GrepError::WorkerPanic(file_name.to_string())),
+        })
+        .collect();
+    results.scheduler_reports = scheduler_reports;
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(line_number: usize) -> Match {
+        Match::new("file", line_number, 0, "line", vec![])
+    }
+
+    fn piece(file: usize, piece: usize, lines: &[usize]) -> Outcome {
+        Outcome::Piece {
+            file,
+            piece,
+            matches: lines.iter().map(|&line| found(line)).collect(),
+        }
+    }
+
+    fn done(file: usize, pieces: usize) -> Outcome {
+        Outcome::FileDone {
+            file,
+            pieces,
+            result: Ok(()),
+        }
+    }
+
+    fn released(sorter: &mut Sorter) -> Vec<usize> {
+        sorter
+            .ready
+            .drain(..)
+            .filter_map(|event| match event {
+                SearchEvent::Match(found) => Some(found.line_number),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn sorter(max_count: Option<usize>, delivery: Delivery) -> Sorter {
+        Sorter::new(vec!["a".into(), "b".into()], max_count, delivery)
+    }
+
+    #[test]
+    fn in_order_waits_for_earlier_pieces_and_files() {
+        let mut sorter = sorter(None, Delivery::InOrder);
+
+        sorter.accept(piece(1, 0, &[7]));
+        sorter.accept(piece(0, 1, &[3]));
+        assert_eq!(released(&mut sorter), Vec::<usize>::new());
+
+        sorter.accept(piece(0, 0, &[1, 2]));
+        assert_eq!(released(&mut sorter), vec![1, 2, 3]);
+
+        sorter.accept(done(1, 1));
+        sorter.accept(done(0, 2));
+        assert_eq!(released(&mut sorter), vec![7]);
+    }
+
+    #[test]
+    fn as_found_only_orders_pieces_when_there_is_a_limit() {
+        let mut unlimited = sorter(None, Delivery::AsFound);
+        unlimited.accept(piece(1, 1, &[7]));
+        unlimited.accept(piece(0, 0, &[1]));
+        assert_eq!(released(&mut unlimited), vec![7, 1]);
+
+        let mut limited = sorter(Some(2), Delivery::AsFound);
+        limited.accept(piece(0, 1, &[3, 4]));
+        limited.accept(piece(1, 0, &[7]));
+        limited.accept(piece(0, 0, &[1]));
+        assert_eq!(released(&mut limited), vec![7, 1, 3]);
+    }
+
+    #[test]
+    fn missing_pieces_are_reported_when_the_search_ends() {
+        let mut sorter = sorter(None, Delivery::InOrder);
+
+        sorter.accept(piece(1, 0, &[7]));
+        sorter.accept(done(1, 1));
+        sorter.accept(done(0, 1));
+        sorter.close();
+
+        let events = sorter.ready.drain(..).collect::<Vec<_>>();
+        assert!(matches!(&events[0], SearchEvent::Begin { path } if path == "a"));
+        assert!(matches!(
+            &events[1],
+            SearchEvent::End { matches: 0, error: Some(GrepError::WorkerPanic(path)), .. }
+                if path == "a"
+        ));
+        assert!(matches!(&events[3], SearchEvent::Match(found) if found.line_number == 7));
+        assert!(matches!(
+            &events[4],
+            SearchEvent::End {
+                matches: 1,
+                error: None,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::walk::glob::Glob;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The `.gitignore` rules that apply inside a directory: its own file, then its parents'.
+pub(crate) struct Gitignore {
+    base: PathBuf,
+    rules: Vec<Rule>,
+    parent: Option<Arc<Gitignore>>,
+}
+
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    directory_only: bool,
+    /// Rules with a `/` before their end are relative to the `.gitignore` directory,
+    /// the rest match a file name at any depth.
+    anchored: bool,
+}
+
+impl Gitignore {
+    pub fn empty() -> Arc<Gitignore> {
+        Arc::new(Gitignore {
+            base: PathBuf::new(),
+            rules: vec![],
+            parent: None,
+        })
+    }
+
+    /// Rules for `directory`, adding its `.gitignore` file to the inherited ones if it has one.
+    pub fn for_directory(self: &Arc<Gitignore>, directory: &Path) -> Arc<Gitignore> {
+        match fs::read_to_string(directory.join(".gitignore")) {
+            Ok(content) => Arc::new(Gitignore {
+                base: directory.to_path_buf(),
+                rules: content.lines().filter_map(parse_rule).collect(),
+                parent: Some(self.clone()),
+            }),
+            Err(_) => self.clone(),
+        }
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_directory: bool) -> bool {
+        let relative = path.strip_prefix(&self.base).ok().map(to_slash_separated);
+
+        // The last matching rule of the innermost file decides
+        let decision = relative.and_then(|relative| {
+            self.rules
+                .iter()
+                .rev()
+                .find(|rule| rule.is_match(&relative, is_directory))
+                .map(|rule| !rule.negated)
+        });
+
+        decision.unwrap_or_else(|| {
+            self.parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_ignored(path, is_directory))
+        })
+    }
+}
+
+impl Rule {
+    fn is_match(&self, relative: &str, is_directory: bool) -> bool {
+        if self.directory_only && !is_directory {
+            return false;
+        }
+        if self.anchored {
+            self.glob.is_match(relative)
+        } else {
+            let file_name = relative.rsplit('/').next().unwrap_or(relative);
+            self.glob.is_match(file_name)
+        }
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (directory_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+
+    Some(Rule {
+        glob: Glob::new(line.strip_prefix('/').unwrap_or(line)),
+        negated,
+        directory_only,
+        anchored,
+    })
+}
+
+fn to_slash_separated(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gitignore(base: &str, content: &str) -> Gitignore {
+        Gitignore {
+            base: PathBuf::from(base),
+            rules: content.lines().filter_map(parse_rule).collect(),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn unanchored_rules_match_names_at_any_depth() {
+        let ignore = gitignore("repo", "*.tmp\n# comment\n\nbuild/");
+
+        assert!(ignore.is_ignored(Path::new("repo/a/b/file.tmp"), false));
+        assert!(ignore.is_ignored(Path::new("repo/src/build"), true));
+        assert!(!ignore.is_ignored(Path::new("repo/src/build"), false));
+    }
+
+    #[test]
+    fn anchored_rules_and_negations() {
+        let ignore = gitignore("repo", "/logs/*.log\n!/logs/keep.log");
+
+        assert!(ignore.is_ignored(Path::new("repo/logs/app.log"), false));
+        assert!(!ignore.is_ignored(Path::new("repo/logs/keep.log"), false));
+        assert!(!ignore.is_ignored(Path::new("repo/other/logs/app.log"), false));
+    }
+}
@@ -0,0 +1,134 @@
+/// Shell-like wildcard pattern, as used by `--include`, `--exclude` and `.gitignore` files.
+///
+/// Supports `?`, `*` (anything but `/`), `**` (anything, `/` included),
+/// `[abc]`, `[a-z]` and `[!a]`. A backslash escapes the next character.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Char(char),
+    AnyChar,
+    Star,
+    DoubleStar,
+    /// `**/`, which also matches no directory at all
+    AnyDirectories,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        let mut tokens = vec![];
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '?' => Token::AnyChar,
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        Token::AnyDirectories
+                    } else {
+                        Token::DoubleStar
+                    }
+                }
+                '*' => Token::Star,
+                '[' => parse_class(&mut chars),
+                '\\' => Token::Char(chars.next().unwrap_or('\\')),
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+        }
+
+        Glob { tokens }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        matches_from(&self.tokens, &text)
+    }
+}
+
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars>) -> Token {
+    let negated = matches!(chars.peek(), Some('!') | Some('^'));
+    if negated {
+        chars.next();
+    }
+
+    let mut ranges = vec![];
+    let mut first = true;
+    while let Some(c) = chars.next() {
+        // A `]` right after the opening bracket is a literal
+        if c == ']' && !first {
+            break;
+        }
+        first = false;
+
+        let mut lookahead = chars.clone();
+        if lookahead.next() == Some('-') && lookahead.peek().is_some_and(|end| *end != ']') {
+            chars.next();
+            let end = chars.next().unwrap();
+            ranges.push((c, end));
+        } else {
+            ranges.push((c, c));
+        }
+    }
+
+    Token::Class { negated, ranges }
+}
+
+fn matches_from(tokens: &[Token], text: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+
+    match token {
+        Token::Char(c) => text.first() == Some(c) && matches_from(rest, &text[1..]),
+        Token::AnyChar => text.first().is_some_and(|c| *c != '/') && matches_from(rest, &text[1..]),
+        Token::Class { negated, ranges } => text.first().is_some_and(|c| {
+            let in_class = ranges.iter().any(|(start, end)| start <= c && c <= end);
+            *c != '/' && in_class != *negated && matches_from(rest, &text[1..])
+        }),
+        Token::Star => (0..=text.len())
+            .take_while(|skipped| *skipped == 0 || text[skipped - 1] != '/')
+            .any(|skipped| matches_from(rest, &text[skipped..])),
+        Token::DoubleStar => (0..=text.len()).any(|skipped| matches_from(rest, &text[skipped..])),
+        Token::AnyDirectories => (0..=text.len())
+            .filter(|skipped| *skipped == 0 || text[skipped - 1] == '/')
+            .any(|skipped| matches_from(rest, &text[skipped..])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_and_question_mark_stay_inside_a_directory() {
+        assert!(Glob::new("*.log").is_match("app.log"));
+        assert!(Glob::new("app.?").is_match("app.1"));
+        assert!(!Glob::new("*.log").is_match("app.log.gz"));
+        assert!(!Glob::new("*.log").is_match("logs/app.log"));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        assert!(Glob::new("**/*.log").is_match("app.log"));
+        assert!(Glob::new("**/*.log").is_match("a/b/app.log"));
+        assert!(Glob::new("logs/**").is_match("logs/a/b"));
+        assert!(!Glob::new("logs/**/*.log").is_match("other/app.log"));
+    }
+
+    #[test]
+    fn classes_support_ranges_and_negation() {
+        assert!(Glob::new("app.[0-9]").is_match("app.7"));
+        assert!(!Glob::new("app.[!0-9]").is_match("app.7"));
+        assert!(Glob::new("[]a]").is_match("]"));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::encoding::{looks_binary, BINARY_SNIFF_SIZE};
+use crate::error::GrepError;
+use crate::gzip::is_gzip;
+use crate::pooling::{default_thread_amount, Spawn, Spawner, WorkerPool};
+use gitignore::Gitignore;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+
+use glob::Glob;
+
+mod gitignore;
+mod glob;
+
+/// Which files a recursive walk hands over to the search.
+pub struct WalkOptions {
+    /// Only file names matching one of these globs are kept, if there are any.
+    pub include: Vec<String>,
+    /// File names matching any of these globs are left out.
+    pub exclude: Vec<String>,
+    pub thread_amount: usize,
+    /// Keeps gzip-compressed files, which would be skipped as binary otherwise.
+    pub decompress: bool,
+    /// Keeps every binary file, for searches that read them as text.
+    pub text: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            include: vec![],
+            exclude: vec![],
+            thread_amount: default_thread_amount(),
+            decompress: false,
+            text: false,
+        }
+    }
+}
+
+/// Files found by `walk`, ready to be passed to any of the `grep_*` strategies.
+#[derive(Debug, Default)]
+pub struct WalkResult {
+    pub files: Vec<String>,
+    pub errors: Vec<GrepError>,
+}
+
+struct NameFilter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+/// Everything a directory task needs, shared by all of them.
+struct WalkContext {
+    filter: NameFilter,
+    spawner: Spawner,
+    found: Sender<(usize, Result<PathBuf, GrepError>)>,
+    decompress: bool,
+    text: bool,
+}
+
+/// Expands directories into the files below them, walking them in parallel.
+///
+/// Hidden entries, entries ignored by a `.gitignore` and binary files are skipped.
+/// Paths that are not directories are kept as given. Files below each root come
+/// out sorted, so the search order does not depend on scheduling.
+pub fn walk(roots: Vec<String>, options: &WalkOptions) -> WalkResult {
+    let pool = WorkerPool::new(options.thread_amount);
+    let (tx, rx) = channel();
+    let context = Arc::new(WalkContext {
+        filter: NameFilter {
+            include: options.include.iter().map(|glob| Glob::new(glob)).collect(),
+            exclude: options.exclude.iter().map(|glob| Glob::new(glob)).collect(),
+        },
+        spawner: pool.spawner(),
+        found: tx,
+        decompress: options.decompress,
+        text: options.text,
+    });
+
+    for (root_index, root) in roots.into_iter().enumerate() {
+        let path = PathBuf::from(&root);
+        if path.is_dir() {
+            let context_clone = context.clone();
+            pool.spawn(move || walk_directory(root_index, path, Gitignore::empty(), context_clone));
+        } else {
+            let _ = context.found.send((root_index, Ok(path)));
+        }
+    }
+
+    // Directory tasks own the remaining spawners and senders through the context
+    drop(context);
+    pool.join();
+
+    let mut found = rx.into_iter().collect::<Vec<_>>();
+    found.sort_by(|(a_root, a), (b_root, b)| {
+        let path = |found: &Result<PathBuf, GrepError>| match found {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(err) => err.path().to_string(),
+        };
+        (a_root, path(a)).cmp(&(b_root, path(b)))
+    });
+
+    let mut result = WalkResult::default();
+    for (_, found) in found {
+        match found {
+            Ok(path) => result.files.push(path.to_string_lossy().to_string()),
+            Err(err) => result.errors.push(err),
+        }
+    }
+    result
+}
+
+fn walk_directory(
+    root_index: usize,
+    directory: PathBuf,
+    gitignore: Arc<Gitignore>,
+    context: Arc<WalkContext>,
+) {
+    let to_grep_error = |path: &Path, err| GrepError::from_io(&path.to_string_lossy(), 0, err);
+    let entries = match fs::read_dir(&directory) {
+        Ok(entries) => entries,
+        Err(err) => {
+            let _ = context
+                .found
+                .send((root_index, Err(to_grep_error(&directory, err))));
+            return;
+        }
+    };
+    let gitignore = gitignore.for_directory(&directory);
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let _ = context
+                    .found
+                    .send((root_index, Err(to_grep_error(&directory, err))));
+                continue;
+            }
+        };
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // Symbolic links are not followed, like `grep -r` does
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                if !gitignore.is_ignored(&path, true) {
+                    let (gitignore, context_clone) = (gitignore.clone(), context.clone());
+                    context
+                        .spawner
+                        .spawn(move || walk_directory(root_index, path, gitignore, context_clone));
+                }
+            }
+            Ok(file_type) if file_type.is_file() => {
+                if !gitignore.is_ignored(&path, false)
+                    && context.filter.accepts(&name)
+                    && (context.text || !is_binary(&path) || context.decompress && is_gzip(&path))
+                {
+                    let _ = context.found.send((root_index, Ok(path)));
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                let _ = context
+                    .found
+                    .send((root_index, Err(to_grep_error(&path, err))));
+            }
+        }
+    }
+}
+
+impl NameFilter {
+    fn accepts(&self, file_name: &str) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(file_name));
+        included && !self.exclude.iter().any(|glob| glob.is_match(file_name))
+    }
+}
+
+/// Files that cannot be read are not considered binary: the search will report them.
+fn is_binary(path: &Path) -> bool {
+    let mut buffer = vec![0; BINARY_SNIFF_SIZE];
+    File::open(path)
+        .and_then(|mut file| file.read(&mut buffer))
+        .is_ok_and(|read_bytes| looks_binary(&buffer[..read_bytes]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walk_tree(include: &[&str], exclude: &[&str]) -> Vec<String> {
+        let options = WalkOptions {
+            include: include.iter().map(|glob| glob.to_string()).collect(),
+            exclude: exclude.iter().map(|glob| glob.to_string()).collect(),
+            thread_amount: 2,
+            decompress: false,
+            text: false,
+        };
+        let result = walk(vec!["resources/tree".to_string()], &options);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        result.files
+    }
+
+    #[test]
+    fn skips_hidden_ignored_and_binary_files() {
+        assert_eq!(
+            walk_tree(&[], &[]),
+            vec![
+                "resources/tree/notes.txt",
+                "resources/tree/server.log",
+                "resources/tree/sub/deep/worker.log",
+                "resources/tree/sub/keep.tmp",
+            ]
+        );
+    }
+
+    #[test]
+    fn include_and_exclude_globs_filter_file_names() {
+        assert_eq!(
+            walk_tree(&["*.log"], &["server*"]),
+            vec!["resources/tree/sub/deep/worker.log"]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::CliErr;
+use crate::CliErr::InvalidNumber;
+
+/// Short flags that take no value, and may therefore be grouped like `-iv`.
+const GROUPABLE_FLAGS: &str = "icvwlLorzaF";
+
+/// Options whose value may be the argument after them, which is then never taken
+/// as an option itself, like the pattern of `-e -v`.
+const VALUE_OPTIONS: &[&str] = &[
+    "-e",
+    "-f",
+    "-m",
+    "-A",
+    "-B",
+    "-C",
+    "--threads",
+    "--fuzzy",
+    "--multiline-lines",
+    "--replace",
+    "--index-file",
+    "--include",
+    "--exclude",
+    "--warmup",
+    "--iterations",
+    "--format",
+];
+
+/// Ends the options, like in grep, so that the arguments after it are taken as they
+/// are, even if they start with `-`.
+const END_OF_OPTIONS: &str = "--";
+
+/// Positions of the arguments that may be options: those before `--` that are not
+/// the value of the option right before them.
+fn option_positions(args: &[String]) -> Vec<usize> {
+    let mut positions = vec![];
+    let mut position = 0;
+
+    while position < args.len() && args[position] != END_OF_OPTIONS {
+        positions.push(position);
+        position += match VALUE_OPTIONS.contains(&args[position].as_str()) {
+            true => 2,
+            false => 1,
+        };
+    }
+
+    positions
+}
+
+/// Splits grouped short flags such as `-icv` into `-i -c -v`.
+pub fn expand_grouped_flags(args: Vec<String>) -> Vec<String> {
+    let positions = option_positions(&args);
+
+    args.into_iter()
+        .enumerate()
+        .flat_map(|(position, arg)| {
+            let is_group = arg.len() > 2
+                && arg.starts_with('-')
+                && !arg.starts_with("--")
+                && arg[1..].chars().all(|flag| GROUPABLE_FLAGS.contains(flag))
+                && positions.contains(&position);
+
+            if is_group {
+                arg[1..].chars().map(|flag| format!("-{}", flag)).collect()
+            } else {
+                vec![arg]
+            }
+        })
+        .collect()
+}
+
+/// Removes the options `take` is true for, leaving alone the arguments after `--`
+/// and the values of other options.
+pub fn take_options(args: &mut Vec<String>, mut take: impl FnMut(&str) -> bool) {
+    let taken = option_positions(args)
+        .into_iter()
+        .filter(|&position| take(&args[position]))
+        .collect::<Vec<_>>();
+
+    for position in taken.into_iter().rev() {
+        args.remove(position);
+    }
+}
+
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    take_options(args, |arg| arg == flag);
+    args.len() != before
+}
+
+/// Removes every `<option> <value>` pair, also written `--option=<value>` for long
+/// options and `-o<value>` for short ones, returning the values.
+pub fn take_values(args: &mut Vec<String>, option: &str) -> Vec<String> {
+    let is_short = !option.starts_with("--");
+    let mut values = vec![];
+    let mut taken = vec![];
+
+    for position in option_positions(args) {
+        let attached =
+            args[position]
+                .strip_prefix(option)
+                .and_then(|rest| match rest.strip_prefix('=') {
+                    Some(value) if !is_short => Some(value),
+                    _ if is_short && !rest.is_empty() => Some(rest),
+                    _ => None,
+                });
+
+        if args[position] == option {
+            taken.push(position);
+            if position + 1 < args.len() {
+                values.push(args[position + 1].clone());
+                taken.push(position + 1);
+            }
+        } else if let Some(value) = attached {
+            values.push(value.to_string());
+            taken.push(position);
+        }
+    }
+
+    for position in taken.into_iter().rev() {
+        args.remove(position);
+    }
+    values
+}
+
+/// Takes the last value of a numeric option, if it was given.
+pub fn take_number(args: &mut Vec<String>, option: &str) -> Result<Option<usize>, CliErr> {
+    let Some(value) = take_values(args, option).pop() else {
+        return Ok(None);
+    };
+
+    value
+        .parse::<usize>()
+        .map(Some)
+        .map_err(|_| InvalidNumber(option.to_string(), value))
+}
+
+/// Removes the `--` ending the options, once they are all taken, leaving the
+/// arguments around it in place.
+pub fn end_options(args: &mut Vec<String>) {
+    if let Some(position) = args.iter().position(|arg| arg == END_OF_OPTIONS) {
+        args.remove(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn values_and_arguments_after_the_end_of_options_are_not_options() {
+        let mut all = expand_grouped_flags(args(&[
+            "-e", "-v", "-iw", "-e", "-iv", "seq", "--", "-c", "-l",
+        ]));
+
+        assert!(!take_flag(&mut all, "-v"));
+        assert!(!take_flag(&mut all, "-c"));
+        assert!(take_flag(&mut all, "-w"));
+        assert_eq!(take_values(&mut all, "-e"), ["-v", "-iv"]);
+        assert!(take_flag(&mut all, "-i"));
+        assert!(!take_flag(&mut all, "-l"));
+        end_options(&mut all);
+        assert_eq!(all, ["seq", "-c", "-l"]);
+
+        let mut all = args(&["-e", "--", "-m", "-1", "seq", "x"]);
+        assert_eq!(take_values(&mut all, "-e"), ["--"]);
+        assert_eq!(take_values(&mut all, "-m"), ["-1"]);
+        assert_eq!(all, ["seq", "x"]);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::CliErr;
+use crate::CliErr::{InvalidNumber, UnknownFormat};
+use crate::args::{take_number, take_values};
+use mini_grep::{Query, SearchResults, Strategy, choose_strategy, search};
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// How `bench` prints its measurements.
+pub enum BenchFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+pub struct BenchOptions {
+    /// Runs per strategy before measuring, to warm up caches.
+    pub warmup: usize,
+    /// Measured runs per strategy.
+    pub iterations: usize,
+    pub format: BenchFormat,
+}
+
+/// Times of every measured run of a strategy, sorted from fastest to slowest.
+struct Measurement {
+    name: &'static str,
+    times: Vec<Duration>,
+}
+
+pub fn take_bench_options(args: &mut Vec<String>) -> Result<BenchOptions, CliErr> {
+    let warmup = take_number(args, "--warmup")?.unwrap_or(1);
+    let iterations = match take_number(args, "--iterations")? {
+        Some(0) => Err(InvalidNumber("--iterations".to_string(), "0".to_string()))?,
+        Some(iterations) => iterations,
+        None => 5,
+    };
+    let format = match take_values(args, "--format").pop().as_deref() {
+        None | Some("table") => BenchFormat::Table,
+        Some("csv") => BenchFormat::Csv,
+        Some("json") => BenchFormat::Json,
+        Some(format) => Err(UnknownFormat(format.to_string()))?,
+    };
+
+    Ok(BenchOptions {
+        warmup,
+        iterations,
+        format,
+    })
+}
+
+/// Runs every strategy over the same files and prints how long each one took.
+/// Returns `false` if any strategy found something different than `seq`.
+pub fn run_bench(
+    query: &Query,
+    file_names: &[String],
+    thread_amount: usize,
+    options: &BenchOptions,
+) -> bool {
+    let strategies = [
+        ("seq", Strategy::Sequential),
+        ("conc", Strategy::PerFile),
+        ("c-chunk", Strategy::Chunked),
+        ("c-range", Strategy::Ranges),
+        ("pool", Strategy::Pool { thread_amount }),
+        ("steal", Strategy::Stealing { thread_amount }),
+        ("auto", choose_strategy(file_names, thread_amount).strategy),
+    ];
+
+    let mut expected: Option<SearchResults> = None;
+    let mut identical = true;
+    let mut measurements = vec![];
+
+    for (name, strategy) in strategies {
+        for _ in 0..options.warmup {
+            search(strategy, query.clone(), file_names.to_vec());
+        }
+
+        let mut times = vec![];
+        for _ in 0..options.iterations {
+            let starting_time = Instant::now();
+            let results = search(strategy, query.clone(), file_names.to_vec());
+            times.push(starting_time.elapsed());
+
+            match &expected {
+                None => expected = Some(results),
+                Some(expected) if !same_results(expected, &results) => {
+                    eprintln!("mini_grep: '{}' found different results than 'seq'", name);
+                    identical = false;
+                }
+                Some(_) => {}
+            }
+        }
+
+        times.sort();
+        measurements.push(Measurement { name, times });
+    }
+
+    let total_bytes = file_names
+        .iter()
+        .map(|file_name| fs::metadata(file_name).map_or(0, |metadata| metadata.len()))
+        .sum();
+    let matches = expected.map_or(0, |results| results.matches.len());
+    match options.format {
+        BenchFormat::Table => print_table(&measurements, file_names, total_bytes, matches, options),
+        BenchFormat::Csv => print_csv(&measurements, total_bytes),
+        BenchFormat::Json => print_json(&measurements, file_names, total_bytes, matches, options),
+    }
+
+    identical
+}
+
+/// Errors hold OS errors, which cannot be compared, so they are compared by message.
+fn same_results(expected: &SearchResults, results: &SearchResults) -> bool {
+    let messages = |results: &SearchResults| {
+        results
+            .errors
+            .iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>()
+    };
+    expected.matches == results.matches && messages(expected) == messages(results)
+}
+
+impl Measurement {
+    fn min(&self) -> f64 {
+        milliseconds(self.times[0])
+    }
+
+    fn median(&self) -> f64 {
+        milliseconds(self.times[self.times.len() / 2])
+    }
+
+    /// Nearest-rank percentile: the time that 95% of the runs did not exceed.
+    fn p95(&self) -> f64 {
+        let rank = (self.times.len() * 95).div_ceil(100);
+        milliseconds(self.times[rank.max(1) - 1])
+    }
+
+    fn max(&self) -> f64 {
+        milliseconds(self.times[self.times.len() - 1])
+    }
+
+    fn throughput(&self, total_bytes: u64) -> f64 {
+        total_bytes as f64 / 1_000_000.0 / (self.median() / 1000.0)
+    }
+
+    /// How many times faster than `seq`, which is measured first.
+    fn speedup(&self, sequential: &Measurement) -> f64 {
+        sequential.median() / self.median()
+    }
+}
+
+fn milliseconds(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+fn print_table(
+    measurements: &[Measurement],
+    file_names: &[String],
+    total_bytes: u64,
+    matches: usize,
+    options: &BenchOptions,
+) {
+    println!(
+        "{} files, {} bytes, {} matches, {} warmup and {} measured runs per strategy\n",
+        file_names.len(),
+        total_bytes,
+        matches,
+        options.warmup,
+        options.iterations
+    );
+    println!(
+        "{:<10}{:>12}{:>12}{:>12}{:>12}{:>12}{:>10}",
+        "strategy", "min ms", "median ms", "p95 ms", "max ms", "MB/s", "speedup"
+    );
+    for measurement in measurements {
+        println!(
+            "{:<10}{:>12.3}{:>12.3}{:>12.3}{:>12.3}{:>12.1}{:>9.2}x",
+            measurement.name,
+            measurement.min(),
+            measurement.median(),
+            measurement.p95(),
+            measurement.max(),
+            measurement.throughput(total_bytes),
+            measurement.speedup(&measurements[0])
+        );
+    }
+}
+
+fn print_csv(measurements: &[Measurement], total_bytes: u64) {
+    println!("strategy,min_ms,median_ms,p95_ms,max_ms,mb_per_s,speedup");
+    for measurement in measurements {
+        println!(
+            "{},{:.3},{:.3},{:.3},{:.3},{:.1},{:.2}",
+            measurement.name,
+            measurement.min(),
+            measurement.median(),
+            measurement.p95(),
+            measurement.max(),
+            measurement.throughput(total_bytes),
+            measurement.speedup(&measurements[0])
+        );
+    }
+}
+
+fn print_json(
+    measurements: &[Measurement],
+    file_names: &[String],
+    total_bytes: u64,
+    matches: usize,
+    options: &BenchOptions,
+) {
+    let strategies = measurements
+        .iter()
+        .map(|measurement| {
+            format!(
+                "{{\"strategy\":\"{}\",\"min_ms\":{:.3},\"median_ms\":{:.3},\"p95_ms\":{:.3},\"max_ms\":{:.3},\"mb_per_s\":{:.1},\"speedup\":{:.2}}}",
+                measurement.name,
+                measurement.min(),
+                measurement.median(),
+                measurement.p95(),
+                measurement.max(),
+                measurement.throughput(total_bytes),
+                measurement.speedup(&measurements[0])
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    println!(
+        "{{\"files\":{},\"bytes\":{},\"matches\":{},\"warmup\":{},\"iterations\":{},\"strategies\":[{}]}}",
+        file_names.len(),
+        total_bytes,
+        matches,
+        options.warmup,
+        options.iterations,
+        strategies
+    );
+}
@@ -0,0 +1,164 @@
+use crate::CliErr;
+use crate::CliErr::UnknownColor;
+use crate::args::take_options;
+use mini_grep::Span;
+use std::env;
+use std::io::{IsTerminal, stdout};
+
+// ANSI codes grep uses by default
+const PATH: &str = "35";
+const LINE_NUMBER: &str = "32";
+const SEPARATOR: &str = "36";
+const MATCH: &str = "1;31";
+// Like `git diff` shows them
+const REMOVED: &str = "31";
+const ADDED: &str = "32";
+const HEADER: &str = "1";
+const ERROR: &str = "31";
+
+/// When to color the output, as given by `--color`.
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/// Paints output with ANSI colors, or leaves it as it is when they are off.
+pub struct Colors {
+    enabled: bool,
+}
+
+/// Takes `--color=<when>`. A bare `--color` means `auto`, like in grep, so it
+/// never takes the next argument as its value.
+pub fn take_color(args: &mut Vec<String>) -> Result<ColorChoice, CliErr> {
+    let mut choice = Ok(ColorChoice::Auto);
+    take_options(args, |arg| {
+        let value = match arg.strip_prefix("--color") {
+            Some("") => "auto",
+            Some(rest) => match rest.strip_prefix('=') {
+                Some(value) => value,
+                None => return false,
+            },
+            None => return false,
+        };
+        choice = match value {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(UnknownColor(value.to_string())),
+        };
+        true
+    });
+    choice
+}
+
+impl Colors {
+    /// With `auto`, colors are only used on a terminal, and never if `NO_COLOR` is set.
+    pub fn new(choice: ColorChoice) -> Colors {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                stdout().is_terminal()
+                    && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        };
+        Colors { enabled }
+    }
+
+    pub fn path(&self, path: &str) -> String {
+        self.paint(PATH, path)
+    }
+
+    pub fn line_number(&self, line_number: usize) -> String {
+        self.paint(LINE_NUMBER, &line_number.to_string())
+    }
+
+    pub fn separator(&self, separator: &str) -> String {
+        self.paint(SEPARATOR, separator)
+    }
+
+    pub fn matched(&self, text: &str) -> String {
+        self.paint(MATCH, text)
+    }
+
+    pub fn removed(&self, line: &str) -> String {
+        self.paint(REMOVED, line)
+    }
+
+    pub fn added(&self, line: &str) -> String {
+        self.paint(ADDED, line)
+    }
+
+    pub fn header(&self, line: &str) -> String {
+        self.paint(HEADER, line)
+    }
+
+    pub fn error(&self, label: &str) -> String {
+        self.paint(ERROR, label)
+    }
+
+    /// Paints the matched parts of `line`.
+    pub fn highlight(&self, line: &str, spans: &[Span]) -> String {
+        if !self.enabled || spans.is_empty() {
+            return line.to_string();
+        }
+
+        let mut highlighted = String::with_capacity(line.len());
+        let mut printed = 0;
+        for span in spans.iter().filter(|span| span.start < span.end) {
+            highlighted.push_str(&line[printed..span.start]);
+            highlighted.push_str(&self.matched(&line[span.start..span.end]));
+            printed = span.end;
+        }
+        highlighted.push_str(&line[printed..]);
+        highlighted
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1B[{}m{}\x1B[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_paints_every_matched_span() {
+        let colors = Colors::new(ColorChoice::Always);
+        let spans = [
+            Span {
+                start: 0,
+                end: 1,
+                pattern: 0,
+                edits: None,
+            },
+            Span {
+                start: 2,
+                end: 2,
+                pattern: 0,
+                edits: None,
+            },
+            Span {
+                start: 4,
+                end: 6,
+                pattern: 1,
+                edits: None,
+            },
+        ];
+
+        assert_eq!(
+            colors.highlight("abcdef", &spans),
+            "\x1B[1;31ma\x1B[0mbcd\x1B[1;31mef\x1B[0m"
+        );
+        assert_eq!(
+            Colors::new(ColorChoice::Never).highlight("abcdef", &spans),
+            "abcdef"
+        );
+    }
+}
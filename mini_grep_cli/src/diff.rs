@@ -0,0 +1,44 @@
+use crate::color::Colors;
+use mini_grep::ReplaceResults;
+use std::time::Duration;
+
+/// Prints what a replacement changed as a unified diff, one hunk per changed line,
+/// then how many lines it changed, or would change without `--in-place`.
+pub fn print_changes(
+    results: &ReplaceResults,
+    colors: &Colors,
+    in_place: bool,
+    elapsed_time: Duration,
+) {
+    for file in &results.files {
+        println!("{}", colors.header(&format!("--- {}", file.path)));
+        println!("{}", colors.header(&format!("+++ {}", file.path)));
+        for change in &file.changes {
+            println!(
+                "{}",
+                colors.separator(&format!(
+                    "@@ -{} +{} @@",
+                    change.line_number, change.line_number
+                ))
+            );
+            println!("{}", colors.removed(&format!("-{}", change.before)));
+            println!("{}", colors.added(&format!("+{}", change.after)));
+        }
+    }
+    results
+        .errors
+        .iter()
+        .for_each(|err| eprintln!("mini_grep: {}", err));
+
+    println!(
+        "\n({} {} lines in {} files in {}ms)",
+        if in_place {
+            "Replaced"
+        } else {
+            "Would replace"
+        },
+        results.changed_lines(),
+        results.files.len(),
+        elapsed_time.as_millis()
+    );
+}
@@ -0,0 +1,93 @@
+use crate::{ERROR, LINES_SELECTED};
+use mini_grep::{Query, TrigramIndex, WalkOptions, update_index, walk};
+use std::io;
+use std::time::Instant;
+
+/// Where 'index' writes the index and '--index' reads it, unless '--index-file' says otherwise.
+pub const DEFAULT_INDEX_FILE: &str = ".mini_grep_index";
+
+/// Indexes the files below `roots`, reading again only those that changed since the
+/// index at `index_file` was last updated, and saves it. Returns the exit code.
+pub fn run_index(
+    index_file: &str,
+    mut roots: Vec<String>,
+    walk_options: &WalkOptions,
+    starting_time: Instant,
+) -> u8 {
+    if roots.is_empty() {
+        roots.push(".".to_string());
+    }
+    let walked = walk(roots, walk_options);
+
+    let previous = match TrigramIndex::load(index_file) {
+        Ok(index) => index,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => TrigramIndex::default(),
+        Err(err) => {
+            eprintln!(
+                "mini_grep: {}: {}, indexing every file again",
+                index_file, err
+            );
+            TrigramIndex::default()
+        }
+    };
+    let update = update_index(previous, walked.files, walk_options.thread_amount);
+    walked
+        .errors
+        .iter()
+        .chain(&update.errors)
+        .for_each(|err| eprintln!("mini_grep: {}", err));
+
+    if let Err(err) = update.index.save(index_file) {
+        eprintln!("mini_grep: {}: {}", index_file, err);
+        return ERROR;
+    }
+    println!(
+        "(Indexed {} files, kept {} unchanged and dropped {} in {}ms)",
+        update.indexed,
+        update.reused,
+        update.dropped,
+        starting_time.elapsed().as_millis()
+    );
+
+    if walked.errors.is_empty() && update.errors.is_empty() {
+        LINES_SELECTED
+    } else {
+        ERROR
+    }
+}
+
+/// The files that may match `query` according to the index at `index_file`. Every
+/// file is kept when the index cannot be read or cannot tell for this pattern.
+pub fn narrow_with_index(
+    index_file: &str,
+    query: &Query,
+    file_names: &[String],
+    verbose: bool,
+) -> Vec<String> {
+    let index = match TrigramIndex::load(index_file) {
+        Ok(index) => index,
+        Err(err) => {
+            eprintln!("mini_grep: {}: {}, searching every file", index_file, err);
+            return file_names.to_vec();
+        }
+    };
+
+    match index.candidates(query, file_names) {
+        Some(candidates) => {
+            if verbose {
+                eprintln!(
+                    "mini_grep: the index narrowed {} files to {}",
+                    file_names.len(),
+                    candidates.len()
+                );
+            }
+            candidates
+        }
+        None => {
+            if verbose {
+                eprintln!("mini_grep: the index cannot narrow this pattern, searching every file");
+            }
+            file_names.to_vec()
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use mini_grep::CancelToken;
+use std::sync::OnceLock;
+
+/// What the terminal sends on Ctrl-C.
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+/// Restores what a signal does by default, i.e. ending the process.
+#[cfg(unix)]
+const SIG_DFL: usize = 0;
+
+static SEARCH: OnceLock<CancelToken> = OnceLock::new();
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+}
+
+/// Cancels `search` on the first Ctrl-C instead of ending the process, so what was
+/// found until then is still printed. A second Ctrl-C ends it right away.
+pub fn cancel_on_interrupt(search: &CancelToken) {
+    if SEARCH.set(search.clone()).is_err() {
+        return;
+    }
+    #[cfg(unix)]
+    unsafe {
+        signal(SIGINT, on_interrupt as extern "C" fn(i32) as usize);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: i32) {
+    // Only atomic stores and `signal` itself, which a signal handler may safely do
+    if let Some(search) = SEARCH.get() {
+        search.cancel();
+    }
+    unsafe {
+        signal(SIGINT, SIG_DFL);
+    }
+}
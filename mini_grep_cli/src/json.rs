@@ -0,0 +1,78 @@
+use mini_grep::{GroupLine, Span};
+
+/// Quotes `text` as a JSON string.
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            character if character.is_control() => {
+                quoted.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A line of output, either a match with its submatches or a line of context.
+pub fn line_record(path: &str, line: &GroupLine) -> String {
+    let common = format!(
+        "\"path\":{},\"line_number\":{},\"byte_offset\":{},\"text\":{}",
+        string(path),
+        line.line_number,
+        line.byte_offset,
+        string(line.line)
+    );
+
+    if !line.is_match {
+        return format!("{{\"type\":\"context\",{}}}", common);
+    }
+    let submatches = line
+        .spans
+        .iter()
+        .map(|span| submatch(line.line, span))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{\"type\":\"match\",{},\"end_line_number\":{},\"submatches\":[{}]}}",
+        common,
+        line.end_line_number(),
+        submatches
+    )
+}
+
+/// Fuzzy submatches also tell how many edits they needed.
+fn submatch(line: &str, span: &Span) -> String {
+    let edits = match span.edits {
+        Some(edits) => format!(",\"edits\":{}", edits),
+        None => String::new(),
+    };
+    format!(
+        "{{\"start\":{},\"end\":{},\"pattern\":{}{},\"text\":{}}}",
+        span.start,
+        span.end,
+        span.pattern,
+        edits,
+        string(&line[span.start..span.end])
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(
+            string("say \"hi\"\\\t\u{1}"),
+            "\"say \\\"hi\\\"\\\\\\t\\u0001\""
+        );
+    }
+}
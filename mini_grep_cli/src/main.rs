@@ -1,84 +1,548 @@
-use crate::CliErr::{MissingFiles, MissingMode, MissingPattern, UnknownMode};
-use mini_grep::{grep_chunk, grep_conc, grep_seq};
+use crate::CliErr::{
+    FollowWithoutFiles, InPlaceCompressed, InPlaceWithoutReplace, InvalidNumber, InvalidPattern,
+    LineByLineOnly, MissingMode, MissingPattern, ReplaceWithoutFiles, UnknownColor, UnknownFormat,
+    UnknownMode, UnreadablePatternFile,
+};
+use args::{end_options, expand_grouped_flags, take_flag, take_number, take_values};
+use bench::{run_bench, take_bench_options};
+use color::{ColorChoice, Colors, take_color};
+use diff::print_changes;
+use index::{DEFAULT_INDEX_FILE, narrow_with_index, run_index};
+use interrupt::cancel_on_interrupt;
+use mini_grep::{
+    CancelToken, DEFAULT_MULTILINE_LINES, Delivery, FollowEvent, GrepError, PatternKind, Query,
+    SearchConfig, SearchEvent, Strategy, WalkOptions, choose_strategy, default_thread_amount,
+    grep_follow, grep_stream_cancellable, replace, stream_reader_cancellable, walk,
+};
+use output::{OutputMode, Printer};
 use std::env;
-use std::env::Args;
-use std::time::Instant;
+use std::fs;
+use std::io::stdin;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+mod args;
+mod bench;
+mod color;
+mod diff;
+mod index;
+mod interrupt;
+mod json;
+mod output;
+
+// Exit codes, following grep
+const LINES_SELECTED: u8 = 0;
+const NO_LINES_SELECTED: u8 = 1;
+const ERROR: u8 = 2;
+// Like a shell reports a process ended by Ctrl-C
+const INTERRUPTED: u8 = 130;
+
+/// How often '--follow' checks the files for new lines.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// The file name that stands for standard input, and how its lines are reported, like grep
+const STDIN: &str = "-";
+const STDIN_LABEL: &str = "(standard input)";
 
 enum CliErr {
     MissingMode,
     MissingPattern,
-    MissingFiles,
     UnknownMode(String),
+    UnknownFormat(String),
+    UnknownColor(String),
+    InvalidPattern(String),
+    UnreadablePatternFile(String, String),
+    InvalidNumber(String, String),
+    ReplaceWithoutFiles,
+    FollowWithoutFiles,
+    InPlaceWithoutReplace,
+    InPlaceCompressed,
+    LineByLineOnly(String),
 }
 
-fn main() {
+fn main() -> ExitCode {
     match run() {
-        Ok(()) => {}
-        Err(MissingMode) => {
-            print_error("No mode was passed. Must be one of 'seq', 'conc' or 'c-chunk.'")
-        }
+        Ok(exit_code) => return exit_code,
+        Err(MissingMode) => print_error(
+            "No mode was passed. Must be one of 'seq', 'conc', 'c-chunk', 'c-range', 'pool', 'steal' or 'auto'.",
+        ),
         Err(MissingPattern) => {
             print_error("No pattern was passed. Must be a string to be searched.")
         }
-        Err(MissingFiles) => print_error("No file names were passed. Must be at least one."),
         Err(UnknownMode(mode)) => print_error(format!("Unknown mode '{}'.", mode).as_str()),
+        Err(UnknownFormat(format)) => print_error(
+            format!(
+                "Unknown format '{}'. Must be one of 'table', 'csv' or 'json'.",
+                format
+            )
+            .as_str(),
+        ),
+        Err(UnknownColor(when)) => print_error(
+            format!(
+                "Unknown color choice '{}'. Must be one of 'auto', 'always' or 'never'.",
+                when
+            )
+            .as_str(),
+        ),
+        Err(InvalidPattern(reason)) => print_error(format!("Invalid pattern: {}", reason).as_str()),
+        Err(UnreadablePatternFile(path, reason)) => {
+            print_error(format!("Could not read the patterns in '{}': {}", path, reason).as_str())
+        }
+        Err(ReplaceWithoutFiles) => {
+            print_error("'--replace' needs files to replace in, not standard input.")
+        }
+        Err(FollowWithoutFiles) => {
+            print_error("'--follow' needs files to follow, not standard input.")
+        }
+        Err(InPlaceWithoutReplace) => {
+            print_error("'--in-place' writes replacements, so it needs '--replace'.")
+        }
+        Err(InPlaceCompressed) => print_error(
+            "'--in-place' cannot write compressed files, so it cannot be used with '-z'.",
+        ),
+        Err(LineByLineOnly(option)) => print_error(
+            format!(
+                "'{}' matches line by line, so it cannot be used with '--multiline'.",
+                option
+            )
+            .as_str(),
+        ),
+        Err(InvalidNumber(option, value)) => print_error(
+            format!(
+                "Invalid value '{}' for '{}'. Must be a positive number.",
+                value, option
+            )
+            .as_str(),
+        ),
     };
+    ExitCode::from(ERROR)
 }
 
+/// Errors may come before `--color` is read, so they are only colored on a terminal.
 fn print_error(message: &str) {
     println!(
-        "{}Error{}: {}
+        "{}: {}
         \nCommand should be:
-        cargo run -- <mode> <pattern> <file 1> <file 2> ... <file n>
+        cargo run -- [options] <mode> <pattern> [<file 1> <file 2> ... <file n>]
+        cargo run -- [options] bench <pattern> <file 1> <file 2> ... <file n>
+        cargo run -- [options] index [<path 1> <path 2> ... <path n>]
         \nWhere:
-        * 'mode' must be one of 'seq', 'conc' or 'c-chunk'
-        * 'pattern' is a string to be searched'
-        * '<file 1> <file 2> ... <file n>' are the paths to the files where the pattern will be searched
+        * 'mode' must be one of 'seq', 'conc', 'c-chunk', 'c-range', 'pool', 'steal' or 'auto'.
+          'steal' queues the chunks of every file on the worker reading it, where idle workers steal them from
+        * 'pattern' is a regular expression to be searched'
+        * '<file 1> <file 2> ... <file n>' are the paths to the files where the pattern will be searched.
+          '-', or no file at all, searches the standard input
+        * 'bench' times every mode over the same files and checks that they all find the same
+        * 'index' builds or updates a trigram index of the files below the paths (the working directory
+          by default), in parallel, reading again only the files whose size or modification time changed
+        \nOptions:
+        * '--' ends the options, so that a pattern or file starting with '-' can follow it
+        * '-e <pattern>' searches for this pattern instead, and may be repeated to search for any of them
+        * '-f <file>' searches for any of the patterns in <file>, one per line
+        * '-i' ignores case, '-w' only matches whole words, '-v' selects the lines that do not match
+        * '-F' takes the patterns as plain text instead of regular expressions
+        * '--multiline' lets matches span up to {} lines, where '\\n' matches the end of a line and '^' / '$'
+          the start / end of any of them. Every match is printed once, with all of its lines
+        * '--multiline-lines <n>' searches like '--multiline', with matches spanning up to <n> lines instead
+        * '--fuzzy <k>' matches the patterns as plain text with up to <k> characters inserted, deleted or
+          replaced, printing the fewest edits of every matching line after its number
+        * '-c' prints the amount of selected lines per file
+        * '-l' / '-L' print the files with / without selected lines
+        * '-m <n>' stops after <n> selected lines per file
+        * '-o' prints only the matched parts of each line
+        * '--json' prints one JSON object per line instead: 'begin' and 'end' for every file,
+          'match' and 'context' for its lines, and a final 'summary' with counts and elapsed time
+        * '-A <n>' / '-B <n>' / '-C <n>' print <n> lines of context after / before / around matches,
+          separating groups of lines that are not next to each other with '--'
+        * '--threads <n>' is the size of the 'pool' and 'steal' mode thread pools (defaults to the number of CPUs)
+        * '--color=<auto|always|never>' highlights matches, file names and line numbers. 'auto', the default,
+          only does on a terminal and when 'NO_COLOR' is not set
+        * '--follow' keeps searching the files as lines are appended to them, like 'tail -f', until Ctrl-C.
+          Truncated and rotated files are followed from their start, every file on a thread of its own
+        * '--replace <text>' previews, as a diff, replacing every match with <text>, where '$1' or '${{name}}'
+          stand for what a group captured and '$0' for the whole match
+        * '--in-place' writes the replacements to the files, each through a temporary file that replaces it.
+          Files whose lines to replace in are not UTF-8 are left as they are, and it cannot be used with '-z'
+        * '--unordered' prints matches as soon as they are found, instead of in file order
+        * '--verbose' prints which strategy 'auto' picked, and why, how many files '--index' left,
+          and how busy every 'steal' worker was
+        * '--warmup <n>' / '--iterations <n>' are the unmeasured / measured runs of each mode in 'bench'
+          (1 and 5 by default), and '--format <table|csv|json>' is how 'bench' prints its results
+        * '-z' searches gzip-compressed files (and standard input) as the text they hold, telling them
+          apart from plain ones by their first bytes
+        * '-r' searches directories recursively, skipping hidden, ignored and binary files
+        * '-a' / '--text' searches binary files, those with a NUL byte near their start, as text.
+          Otherwise only 'Binary file <file> matches' is printed for them, like grep, and '-r' skips them.
+          Lines that are not UTF-8 are read as Latin-1, and files starting with a UTF-16 byte order mark
+          are transcoded
+        * '--include <glob>' / '--exclude <glob>' filter the file names found by '-r'
+        * '--index' skips the files that the index proves cannot match, searching every file for patterns
+          it cannot narrow. Files must be named as they were when indexed, like by the same '-r' paths
+        * '--index-file <path>' is where 'index' writes the index and '--index' reads it
+          ('.mini_grep_index' by default)
         ",
-        "\x1B[31m",
-        "\x1B[0m",
-         message
+        Colors::new(ColorChoice::Auto).error("Error"),
+        message,
+        DEFAULT_MULTILINE_LINES
     );
 }
 
-fn run() -> Result<(), CliErr> {
-    let mut args = env::args();
+fn run() -> Result<ExitCode, CliErr> {
     // First one is the command name and is unused
-    args.next();
+    let mut args = expand_grouped_flags(env::args().skip(1).collect::<Vec<String>>());
+    let thread_amount = match take_number(&mut args, "--threads")? {
+        Some(0) => Err(InvalidNumber("--threads".to_string(), "0".to_string()))?,
+        Some(amount) => amount,
+        None => default_thread_amount(),
+    };
+    let delivery = if take_flag(&mut args, "--unordered") {
+        Delivery::AsFound
+    } else {
+        Delivery::InOrder
+    };
+    let verbose = take_flag(&mut args, "--verbose");
+    let follow = take_flag(&mut args, "--follow");
+    let replacement = take_values(&mut args, "--replace").pop();
+    let in_place = take_flag(&mut args, "--in-place");
+    let use_index = take_flag(&mut args, "--index");
+    let index_file = take_values(&mut args, "--index-file")
+        .pop()
+        .unwrap_or(DEFAULT_INDEX_FILE.to_string());
+    let bench_options = take_bench_options(&mut args)?;
+    let recursive = take_flag(&mut args, "-r");
+    let decompress = take_flag(&mut args, "-z");
+    if in_place {
+        if replacement.is_none() {
+            return Err(InPlaceWithoutReplace);
+        }
+        if decompress {
+            return Err(InPlaceCompressed);
+        }
+    }
+    let text = [take_flag(&mut args, "-a"), take_flag(&mut args, "--text")].contains(&true);
+    let walk_options = WalkOptions {
+        include: take_values(&mut args, "--include"),
+        exclude: take_values(&mut args, "--exclude"),
+        thread_amount,
+        decompress,
+        text,
+    };
+    let case_insensitive = take_flag(&mut args, "-i");
+    let whole_word = take_flag(&mut args, "-w");
+    let multiline = take_flag(&mut args, "--multiline");
+    let multiline = match take_number(&mut args, "--multiline-lines")? {
+        Some(0) => Err(InvalidNumber(
+            "--multiline-lines".to_string(),
+            "0".to_string(),
+        ))?,
+        Some(max_lines) => Some(max_lines),
+        None => multiline.then_some(DEFAULT_MULTILINE_LINES),
+    };
+    if multiline.is_some() {
+        if follow {
+            return Err(LineByLineOnly("--follow".to_string()));
+        }
+        if replacement.is_some() {
+            return Err(LineByLineOnly("--replace".to_string()));
+        }
+    }
+    let fuzzy = take_number(&mut args, "--fuzzy")?;
+    let kind = match take_flag(&mut args, "-F") {
+        true => PatternKind::Literal,
+        false => PatternKind::Regex,
+    };
+    let colors = Colors::new(take_color(&mut args)?);
+    let mut output_mode = take_output_mode(&mut args);
+    let context = take_number(&mut args, "-C")?.unwrap_or(0);
+    let invert = take_flag(&mut args, "-v");
+    let mut max_count = take_number(&mut args, "-m")?;
+    let before_context = take_number(&mut args, "-B")?.unwrap_or(context);
+    let after_context = take_number(&mut args, "-A")?.unwrap_or(context);
+    match &mut output_mode {
+        // Listing files only needs to know whether there is a first match
+        OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatches => max_count = Some(1),
+        OutputMode::Lines { separate_groups } => {
+            *separate_groups = before_context > 0 || after_context > 0
+        }
+        _ => {}
+    }
+    let mut expressions = take_values(&mut args, "-e");
+    let pattern_files = take_values(&mut args, "-f");
+    for pattern_file in &pattern_files {
+        expressions.extend(read_patterns(pattern_file)?);
+    }
+    end_options(&mut args);
+    let mut args = args.into_iter();
 
     let mode = args.next().ok_or(MissingMode)?;
-    let pattern = args.next().ok_or(MissingPattern)?;
-    let file_names: Vec<String> = get_remaining(&mut args)?;
+    if mode == "index" {
+        let roots = args.collect();
+        let exit_code = run_index(&index_file, roots, &walk_options, Instant::now());
+        return Ok(ExitCode::from(exit_code));
+    }
+    // 'auto' can only choose once the files to search are known, and 'bench' tries them all
+    let is_bench = mode == "bench";
+    let strategy = match mode.as_str() {
+        "seq" => Some(Strategy::Sequential),
+        "conc" => Some(Strategy::PerFile),
+        "c-chunk" => Some(Strategy::Chunked),
+        "c-range" => Some(Strategy::Ranges),
+        "pool" => Some(Strategy::Pool { thread_amount }),
+        "steal" => Some(Strategy::Stealing { thread_amount }),
+        "auto" | "bench" => None,
+        _ => Err(UnknownMode(mode))?,
+    };
+    // An empty pattern file means no pattern at all, which matches nothing, like grep
+    if expressions.is_empty() && pattern_files.is_empty() {
+        expressions.push(args.next().ok_or(MissingPattern)?);
+    }
+    let query = SearchConfig::any_of(expressions)
+        .kind(kind)
+        .case_insensitive(case_insensitive)
+        .whole_word(whole_word)
+        .fuzzy(fuzzy)
+        .multiline(multiline)
+        .invert(invert)
+        .max_count(max_count)
+        .before_context(before_context)
+        .after_context(after_context)
+        .decompress(decompress)
+        .text(text)
+        .query()
+        .map_err(|err| InvalidPattern(err.to_string()))?;
 
     let starting_time = Instant::now();
 
-    let result = match mode.as_str() {
-        "seq" => grep_seq(pattern.clone(), file_names),
-        "conc" => grep_conc(pattern.clone(), file_names),
-        "c-chunk" => grep_chunk(pattern.clone(), file_names),
-        _ => Err(UnknownMode(mode.clone()))?,
+    let mut walk_errors = vec![];
+    let file_names: Vec<String> = if recursive {
+        let mut roots = args.collect::<Vec<String>>();
+        // Like grep, a recursive search without paths searches the working directory
+        if roots.is_empty() {
+            roots.push(".".to_string());
+        }
+        let walked = walk(roots, &walk_options);
+        walk_errors = walked.errors;
+        walked.files
+    } else {
+        let mut file_names = args.collect::<Vec<String>>();
+        if file_names.is_empty() {
+            file_names.push(STDIN.to_string());
+        }
+        file_names
+    };
+
+    if is_bench {
+        walk_errors
+            .iter()
+            .for_each(|err| eprintln!("mini_grep: {}", err));
+        let identical = run_bench(&query, &file_names, thread_amount, &bench_options);
+        return Ok(ExitCode::from(if identical {
+            LINES_SELECTED
+        } else {
+            ERROR
+        }));
+    }
+
+    let cancel = CancelToken::new();
+    cancel_on_interrupt(&cancel);
+
+    if follow {
+        if file_names.iter().any(|file_name| file_name == STDIN) {
+            return Err(FollowWithoutFiles);
+        }
+        let events = follow_events(query, file_names.clone(), &cancel);
+        return Ok(print_all(
+            events,
+            &cancel,
+            colors,
+            walk_errors,
+            &file_names,
+            &output_mode,
+            starting_time,
+        ));
+    }
+
+    // Files left out are still listed by '-c' and '-L', as files without matches
+    let searched_files = if use_index {
+        narrow_with_index(&index_file, &query, &file_names, verbose)
+    } else {
+        file_names.clone()
     };
+    let strategy = strategy.unwrap_or_else(|| {
+        let choice = choose_strategy(&searched_files, thread_amount);
+        if verbose {
+            eprintln!("mini_grep: {}", choice);
+        }
+        choice.strategy
+    });
+    if let Some(replacement) = replacement {
+        if file_names.iter().any(|file_name| file_name == STDIN) {
+            return Err(ReplaceWithoutFiles);
+        }
+        let results = replace(
+            strategy,
+            query,
+            replacement.as_str(),
+            searched_files,
+            in_place,
+        );
+        print_changes(&results, &colors, in_place, starting_time.elapsed());
+        return Ok(ExitCode::from(
+            match (results.has_errors(), results.files.is_empty()) {
+                (true, _) => ERROR,
+                (false, false) => LINES_SELECTED,
+                (false, true) => NO_LINES_SELECTED,
+            },
+        ));
+    }
+    // Work stealing reports how busy its workers were once the search is over
+    let events = search_inputs(strategy, query, searched_files, delivery, &cancel).filter_map(
+        move |event| match event {
+            SearchEvent::Scheduled(report) => {
+                if verbose {
+                    report
+                        .to_string()
+                        .lines()
+                        .for_each(|line| eprintln!("mini_grep: {}", line));
+                }
+                None
+            }
+            event => Some(event),
+        },
+    );
+    let file_names = file_names
+        .into_iter()
+        .map(|file_name| match file_name.as_str() {
+            STDIN => STDIN_LABEL.to_string(),
+            _ => file_name,
+        })
+        .collect::<Vec<_>>();
 
-    print_all(result, starting_time)
+    let exit_code = print_all(
+        events,
+        &cancel,
+        colors,
+        walk_errors,
+        &file_names,
+        &output_mode,
+        starting_time,
+    );
+    Ok(exit_code)
 }
 
-fn get_remaining(args: &mut Args) -> Result<Vec<String>, CliErr> {
-    let file_names = args.collect::<Vec<String>>();
+fn take_output_mode(args: &mut Vec<String>) -> OutputMode {
+    // Every flag is taken, so none of them is left behind as a file name
+    let flags = ["--json", "-c", "-l", "-L", "-o"].map(|flag| take_flag(args, flag));
 
-    if file_names.is_empty() {
-        return Err(MissingFiles);
+    match flags {
+        [true, ..] => OutputMode::Json,
+        [_, true, ..] => OutputMode::Count,
+        [_, _, true, ..] => OutputMode::FilesWithMatches,
+        [_, _, _, true, _] => OutputMode::FilesWithoutMatches,
+        [_, _, _, _, true] => OutputMode::OnlyMatching,
+        _ => OutputMode::Lines {
+            separate_groups: false,
+        },
     }
-    Ok(file_names)
 }
 
-fn print_all(filtered_lines: Vec<String>, starting_time: Instant) -> Result<(), CliErr> {
-    let elapsed_time = starting_time.elapsed().as_millis();
+/// Reads one pattern per line.
+fn read_patterns(path: &str) -> Result<Vec<String>, CliErr> {
+    let patterns = fs::read_to_string(path)
+        .map_err(|err| UnreadablePatternFile(path.to_string(), err.to_string()))?;
+    Ok(patterns.lines().map(String::from).collect())
+}
 
-    filtered_lines.iter().for_each(|line| println!("{}", line));
-    println!(
-        "\n(Found {} matches in {}ms)",
-        filtered_lines.len(),
-        elapsed_time
-    );
-    Ok(())
+/// Searches the files in order, reading standard input wherever '-' is among them.
+/// Every search starts once the previous one is over, so their events come in order.
+/// Files are searched with `searcher`, and standard input with `strategy`, since a
+/// reader can only be split the ways the library knows of.
+fn search_inputs(
+    strategy: Strategy,
+    query: Query,
+    file_names: Vec<String>,
+    delivery: Delivery,
+    cancel: &CancelToken,
+) -> impl Iterator<Item = SearchEvent> {
+    let mut inputs: Vec<Vec<String>> = vec![];
+    for file_name in file_names {
+        match inputs.last_mut() {
+            Some(files) if file_name != STDIN && files[0] != STDIN => files.push(file_name),
+            _ => inputs.push(vec![file_name]),
+        }
+    }
+
+    let cancel = cancel.clone();
+    inputs.into_iter().flat_map(move |files| {
+        if files[0] == STDIN {
+            let reader = stdin();
+            stream_reader_cancellable(
+                strategy,
+                query.clone(),
+                STDIN_LABEL,
+                reader,
+                delivery,
+                &cancel,
+            )
+            .events()
+        } else {
+            grep_stream_cancellable(strategy, query.clone(), files, delivery, &cancel).events()
+        }
+    })
+}
+
+/// Follows the files as they grow, until Ctrl-C. What happens to a file apart
+/// from its matches is only reported on the standard error, like `tail -f` does.
+fn follow_events(
+    query: Query,
+    file_names: Vec<String>,
+    cancel: &CancelToken,
+) -> impl Iterator<Item = SearchEvent> {
+    grep_follow(query, file_names, FOLLOW_POLL_INTERVAL, cancel).filter_map(|event| match event {
+        FollowEvent::Begin { path } => Some(SearchEvent::Begin { path }),
+        FollowEvent::Match(found) => Some(SearchEvent::Match(found)),
+        FollowEvent::Truncated { path } => {
+            eprintln!("mini_grep: {}: file truncated", path);
+            None
+        }
+        FollowEvent::Rotated { path } => {
+            eprintln!("mini_grep: {}: file replaced, following the new one", path);
+            None
+        }
+        FollowEvent::End {
+            path,
+            matches,
+            error,
+        } => Some(SearchEvent::End {
+            path,
+            matches,
+            error,
+        }),
+    })
+}
+
+/// Prints matches as the search finds them, and how it went once it is over.
+fn print_all(
+    events: impl Iterator<Item = SearchEvent>,
+    cancel: &CancelToken,
+    colors: Colors,
+    walk_errors: Vec<GrepError>,
+    file_names: &[String],
+    output_mode: &OutputMode,
+    starting_time: Instant,
+) -> ExitCode {
+    let mut printer = Printer::new(output_mode, colors);
+
+    walk_errors.iter().for_each(|err| printer.print_error(err));
+    events.for_each(|event| printer.print_event(event));
+
+    let summary = printer.finish(file_names, starting_time.elapsed());
+    if cancel.is_cancelled() {
+        return ExitCode::from(INTERRUPTED);
+    }
+    match (summary.has_errors, summary.selected) {
+        (true, _) => ExitCode::from(ERROR),
+        (false, true) => ExitCode::from(LINES_SELECTED),
+        (false, false) => ExitCode::from(NO_LINES_SELECTED),
+    }
 }
@@ -0,0 +1,269 @@
+use crate::color::Colors;
+use crate::json;
+use mini_grep::{ContextGrouper, GrepError, Match, SearchEvent, Span};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// What gets printed for the lines a search reports.
+pub enum OutputMode {
+    /// The reported lines. With context, groups of lines that are not next to each
+    /// other are separated by `--`, like grep does.
+    Lines { separate_groups: bool },
+    /// Only the matched parts of each line, like `grep -o`.
+    OnlyMatching,
+    /// The amount of reported lines per file, like `grep -c`.
+    Count,
+    /// The files with at least one reported line, like `grep -l`.
+    FilesWithMatches,
+    /// The files without any reported line, like `grep -L`.
+    FilesWithoutMatches,
+    /// One JSON object per line: every match and context line, where every file
+    /// begins and ends, and a summary of the whole search.
+    Json,
+}
+
+/// How a search went, once everything was printed.
+pub struct Summary {
+    /// Whether anything was selected, which decides the exit code along with errors.
+    pub selected: bool,
+    pub has_errors: bool,
+}
+
+/// Prints what a search finds while it is still running. What depends on whole
+/// files, like the amount of lines per file, is printed by `finish`.
+pub struct Printer<'a> {
+    mode: &'a OutputMode,
+    colors: Colors,
+    grouper: ContextGrouper,
+    counts: HashMap<String, usize>,
+    failed_files: HashSet<String>,
+    /// Binary files already told to match, which is all that is printed of them.
+    binary_files: HashSet<String>,
+    matches: usize,
+    errors: usize,
+    files: usize,
+    files_with_matches: usize,
+    listed: bool,
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(mode: &'a OutputMode, colors: Colors) -> Printer<'a> {
+        Printer {
+            mode,
+            colors,
+            grouper: ContextGrouper::default(),
+            counts: HashMap::new(),
+            failed_files: HashSet::new(),
+            binary_files: HashSet::new(),
+            matches: 0,
+            errors: 0,
+            files: 0,
+            files_with_matches: 0,
+            listed: false,
+        }
+    }
+
+    pub fn print_event(&mut self, event: SearchEvent) {
+        match event {
+            SearchEvent::Begin { path } => {
+                if let OutputMode::Json = self.mode {
+                    println!("{{\"type\":\"begin\",\"path\":{}}}", json::string(&path));
+                }
+            }
+            SearchEvent::Match(found) => self.print_match(&found),
+            // Only '--verbose' prints how busy the workers were
+            SearchEvent::Scheduled(_) => {}
+            SearchEvent::End {
+                path,
+                matches,
+                error,
+            } => {
+                self.files += 1;
+                if matches > 0 {
+                    self.files_with_matches += 1;
+                }
+                if let Some(err) = &error {
+                    self.print_error(err);
+                }
+                if let OutputMode::Json = self.mode {
+                    let error =
+                        error.map_or("null".to_string(), |err| json::string(&err.to_string()));
+                    println!(
+                        "{{\"type\":\"end\",\"path\":{},\"matches\":{},\"error\":{}}}",
+                        json::string(&path),
+                        matches,
+                        error
+                    );
+                }
+            }
+        }
+    }
+
+    fn print_match(&mut self, found: &Match) {
+        self.matches += 1;
+        let count = self.counts.entry(found.path.clone()).or_insert(0);
+        *count += 1;
+
+        // Like grep, the lines of binary files are not printed, only that they match
+        if found.binary
+            && matches!(
+                self.mode,
+                OutputMode::Lines { .. } | OutputMode::OnlyMatching
+            )
+        {
+            if self.binary_files.insert(found.path.clone()) {
+                println!("Binary file {} matches", self.colors.path(&found.path));
+            }
+            return;
+        }
+
+        match self.mode {
+            OutputMode::Lines { separate_groups } => self.print_lines(found, *separate_groups),
+            OutputMode::OnlyMatching => found.spans.iter().for_each(|span| {
+                println!(
+                    "{}{}{}{}{}{}",
+                    self.colors.path(&found.path),
+                    self.colors.separator(":"),
+                    self.colors.line_number(found.line_number),
+                    self.colors.separator(":"),
+                    self.edits(span.edits),
+                    self.colors.matched(&found.line[span.start..span.end])
+                )
+            }),
+            OutputMode::FilesWithMatches if *count == 1 => {
+                println!("{}", self.colors.path(&found.path));
+                self.listed = true;
+            }
+            OutputMode::Json => {
+                let (_, lines) = self.grouper.push(found);
+                lines
+                    .iter()
+                    .for_each(|line| println!("{}", json::line_record(&found.path, line)));
+            }
+            _ => {}
+        }
+    }
+
+    /// Like grep, files that could not be searched are only warned about.
+    /// Files left unfinished by Ctrl-C are not, since the user asked for it.
+    pub fn print_error(&mut self, err: &GrepError) {
+        if !matches!(err, GrepError::Cancelled(_)) {
+            eprintln!("mini_grep: {}", err);
+        }
+        self.errors += 1;
+        self.failed_files.insert(err.path().to_string());
+    }
+
+    /// Prints what could only be printed once the search is over, ending with
+    /// how many matches it found and how long it took.
+    pub fn finish(self, file_names: &[String], elapsed_time: Duration) -> Summary {
+        let searched_files = file_names
+            .iter()
+            .filter(|file_name| !self.failed_files.contains(file_name.as_str()));
+
+        let selected = match self.mode {
+            OutputMode::Count => {
+                searched_files.for_each(|file_name| {
+                    println!(
+                        "{}{}{}",
+                        self.colors.path(file_name),
+                        self.colors.separator(":"),
+                        self.counts.get(file_name).unwrap_or(&0)
+                    )
+                });
+                self.matches > 0
+            }
+            OutputMode::FilesWithMatches => self.listed,
+            OutputMode::FilesWithoutMatches => {
+                let mut listed = false;
+                searched_files
+                    .filter(|file_name| !self.counts.contains_key(file_name.as_str()))
+                    .for_each(|file_name| {
+                        println!("{}", self.colors.path(file_name));
+                        listed = true;
+                    });
+                listed
+            }
+            OutputMode::Lines { .. } | OutputMode::OnlyMatching | OutputMode::Json => {
+                self.matches > 0
+            }
+        };
+
+        if let OutputMode::Json = self.mode {
+            println!(
+                "{{\"type\":\"summary\",\"elapsed_ms\":{:.3},\"files\":{},\"files_with_matches\":{},\"matches\":{},\"errors\":{}}}",
+                elapsed_time.as_secs_f64() * 1000.0,
+                self.files,
+                self.files_with_matches,
+                self.matches,
+                self.errors
+            );
+        } else {
+            println!(
+                "\n(Found {} matches in {}ms)",
+                self.matches,
+                elapsed_time.as_millis()
+            );
+        }
+
+        Summary {
+            selected,
+            has_errors: !self.failed_files.is_empty(),
+        }
+    }
+
+    /// The edits a fuzzy match needed, as a field of its own before the text.
+    fn edits(&self, edits: Option<usize>) -> String {
+        match edits {
+            Some(edits) => format!("edits={}{}", edits, self.colors.separator(":")),
+            None => String::new(),
+        }
+    }
+
+    /// Prints a match with its context. Context shared by close matches is printed once.
+    fn print_lines(&mut self, found: &Match, separate_groups: bool) {
+        let printed_before = self.matches > 1;
+        let (starts_group, lines) = self.grouper.push(found);
+
+        if separate_groups && starts_group && printed_before {
+            println!("{}", self.colors.separator("--"));
+        }
+        for line in lines {
+            let separator = self.colors.separator(if line.is_match { ":" } else { "-" });
+            for (index, (text, spans)) in split_lines(line.line, line.spans).iter().enumerate() {
+                let edits = spans.iter().filter_map(|span| span.edits).min();
+                println!(
+                    "{}{}{}{}{}{}",
+                    self.colors.path(&found.path),
+                    separator,
+                    self.colors.line_number(line.line_number + index),
+                    separator,
+                    self.edits(edits),
+                    self.colors.highlight(text, spans)
+                );
+            }
+        }
+    }
+}
+
+/// The lines of a match, which multiline searches may find across several of them,
+/// each with the part of every span inside it.
+fn split_lines<'a>(line: &'a str, spans: &[Span]) -> Vec<(&'a str, Vec<Span>)> {
+    let mut start = 0;
+    line.split('\n')
+        .map(|text| {
+            let end = start + text.len();
+            let spans = spans
+                .iter()
+                .filter(|span| span.start <= end && (span.end > start || span.start >= start))
+                .map(|span| Span {
+                    start: span.start.max(start) - start,
+                    end: span.end.min(end) - start,
+                    ..*span
+                })
+                .collect();
+            start = end + 1;
+            (text, spans)
+        })
+        .collect()
+}